@@ -0,0 +1,318 @@
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use ordermap::OrderMap;
+use serde::Deserialize;
+
+use crate::halt::HaltCondition;
+use crate::lint::lint_directory;
+use crate::lut::Lut;
+use crate::netlist::Netlist;
+use crate::obfuscation::ObfuscationConfig;
+use crate::registry::Registry;
+use crate::scope::global::GlobalScope;
+
+/// Settings for the whole pipeline, usually read from a `vlogpp.toml`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// SystemVerilog source files
+    pub sources: Vec<PathBuf>,
+    /// Modules to emit, instead of the top modules found by yosys
+    #[serde(default)]
+    pub tops: Vec<String>,
+    /// Parameter overrides by module and parameter name
+    #[serde(default)]
+    pub params: OrderMap<String, OrderMap<String, toml::Value>>,
+    /// Luts which are registered next to the modules
+    #[serde(default)]
+    pub luts: Vec<LutConfig>,
+    /// Sequential modules which get a repeat macro
+    #[serde(default)]
+    pub repeat: Vec<RepeatConfig>,
+    #[serde(default)]
+    pub lint: LintConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub obfuscation: ObfuscationConfig,
+    /// Shortest run of arguments which is replaced by `__VA_ARGS__`, or 0 to keep all arguments
+    #[serde(default = "default_variadic_threshold")]
+    pub variadic_threshold: usize,
+    /// Number of times each `EVAL` level nests the level below it
+    #[serde(default = "default_eval_branching")]
+    pub eval_branching: usize,
+    /// Show the synthesized netlist in a viewer
+    #[serde(skip)]
+    pub show_netlist: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LutConfig {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    /// Values of all outputs for each row, where the first input is the most significant bit
+    pub table: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatConfig {
+    /// Module to repeat, or every top module if missing
+    pub module: Option<String>,
+    pub halt: HaltCondition,
+    /// Emit a `RUN_` macro with rescans for this many cycles instead of the bare repeat macro. Runs
+    /// which don't halt in time leave an unexpanded call.
+    pub max_cycles: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LintConfig {
+    #[serde(default = "default_lint")]
+    pub enabled: bool,
+    /// Files or directories to lint, instead of the sources
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// File to write the macros to, instead of stdout
+    pub path: Option<PathBuf>,
+    /// Include guard around the macros
+    pub guard: Option<String>,
+}
+
+impl Config {
+    pub fn new(sources: Vec<PathBuf>) -> Self {
+        Self {
+            sources,
+            tops: Vec::new(),
+            params: OrderMap::new(),
+            luts: Vec::new(),
+            repeat: Vec::new(),
+            lint: LintConfig::default(),
+            output: OutputConfig::default(),
+            obfuscation: ObfuscationConfig::default(),
+            variadic_threshold: default_variadic_threshold(),
+            eval_branching: default_eval_branching(),
+            show_netlist: false,
+        }
+    }
+
+    /// Reads a config file, where paths are relative to the directory of the file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|err| format!("Invalid {}: {err}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for path in config
+            .sources
+            .iter_mut()
+            .chain(config.lint.paths.iter_mut())
+            .chain(config.output.path.iter_mut())
+            .chain(config.obfuscation.key.iter_mut())
+        {
+            *path = base.join(&path);
+        }
+
+        Ok(config)
+    }
+
+    /// Runs the pipeline from linting the sources to the finished macros
+    pub fn build(&self) -> Result<GlobalScope, String> {
+        if self.eval_branching < 2 {
+            return Err("The EVAL branching has to be at least 2".to_string());
+        }
+        if self
+            .repeat
+            .iter()
+            .any(|repeat| repeat.max_cycles == Some(0))
+        {
+            return Err("A repeat needs at least 1 cycle".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.obfuscation.logic_intensity) {
+            return Err("The logic intensity has to be between 0 and 1".to_string());
+        }
+        if self.obfuscation.key.is_some() && !self.obfuscation.enabled {
+            return Err("A key file needs obfuscation".to_string());
+        }
+        if self.obfuscation.scramble_wires && !self.obfuscation.enabled {
+            return Err("Scrambling the wires needs obfuscation".to_string());
+        }
+
+        let mut params = Vec::new();
+        for (module, module_params) in &self.params {
+            for (param, value) in module_params {
+                let value = match value {
+                    toml::Value::Integer(value) => value.to_string(),
+                    toml::Value::String(value) => value.clone(),
+                    _ => {
+                        return Err(format!(
+                            "Parameter `{module}.{param}` has to be an integer or a string"
+                        ));
+                    }
+                };
+                params.push((param.as_str(), value, module.as_str()));
+            }
+        }
+
+        if self.lint.enabled {
+            let paths = if self.lint.paths.is_empty() {
+                &self.sources
+            } else {
+                &self.lint.paths
+            };
+            for path in paths {
+                lint_directory(path)?;
+            }
+        }
+
+        let netlist = Netlist::from_files(
+            &self.sources,
+            self.show_netlist,
+            &params
+                .iter()
+                .map(|(param, value, module)| (*param, value.as_str(), *module))
+                .collect::<Vec<_>>(),
+        )?;
+        let mut registry = Registry::default()
+            .add_netlist(netlist)
+            .eval_branching(self.eval_branching);
+        for lut in &self.luts {
+            registry = registry.register_lut(lut.to_lut()?);
+        }
+
+        let mut global_scope = GlobalScope::new(registry, self.obfuscation.clone());
+        let tops = if self.tops.is_empty() {
+            Registry::top_modules(&mut global_scope)
+        } else {
+            self.tops
+                .iter()
+                .map(|name| {
+                    Registry::module(&mut global_scope, name)
+                        .ok_or_else(|| format!("Unknown module `{name}`"))
+                })
+                .collect::<Result<_, _>>()?
+        };
+        if tops.is_empty() {
+            return Err("No top module found".to_string());
+        }
+
+        for repeat in &self.repeat {
+            let modules = match &repeat.module {
+                Some(name) => {
+                    vec![
+                        Registry::module(&mut global_scope, name)
+                            .ok_or_else(|| format!("Unknown module `{name}`"))?,
+                    ]
+                }
+                None => tops.clone(),
+            };
+
+            for module in modules {
+                match repeat.max_cycles {
+                    Some(max_cycles) => {
+                        Registry::run_macro(&mut global_scope, module, &repeat.halt, max_cycles)?
+                    }
+                    None => Registry::repeat_macro(&mut global_scope, module, &repeat.halt)?,
+                };
+            }
+        }
+
+        if self.variadic_threshold != 0 {
+            global_scope.variadicify_macros(self.variadic_threshold);
+        }
+
+        Ok(global_scope)
+    }
+
+    /// Writes the macros to the output file, or to stdout, and the key file if there is one
+    pub fn write(&self, global_scope: &GlobalScope) -> Result<(), String> {
+        if let Some(path) = &self.obfuscation.key {
+            global_scope.obfuscation_key().write(path)?;
+        }
+
+        let text = if self.output.path.is_none() && std::io::stdout().is_terminal() {
+            format!("{global_scope}")
+        } else {
+            global_scope.emit()
+        };
+        let text = match &self.output.guard {
+            Some(guard) => format!("#ifndef {guard}\n#define {guard}\n{text}#endif\n"),
+            None => text,
+        };
+
+        match &self.output.path {
+            Some(path) => {
+                fs::write(path, text)
+                    .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+            }
+            None => {
+                print!("{text}");
+                Ok(())
+            }
+        }
+    }
+}
+
+impl LutConfig {
+    pub fn to_lut(&self) -> Result<Lut, String> {
+        let rows = 1_usize
+            .checked_shl(self.inputs.len() as u32)
+            .ok_or_else(|| format!("Lut `{}` has too many inputs", self.name))?;
+        if self.table.len() != rows * self.outputs.len() {
+            return Err(format!(
+                "Lut `{}` needs {} table entries",
+                self.name,
+                rows * self.outputs.len()
+            ));
+        }
+        if self.table.iter().any(|&value| value > 1) {
+            return Err(format!(
+                "Lut `{}` has a table entry other than 0 or 1",
+                self.name
+            ));
+        }
+
+        Ok(Lut::new(
+            &self.name,
+            &self.inputs.iter().map(String::as_str).collect::<Vec<_>>(),
+            &self.outputs.iter().map(String::as_str).collect::<Vec<_>>(),
+            &self
+                .table
+                .iter()
+                .map(|&value| value == 1)
+                .collect::<Vec<_>>(),
+        ))
+    }
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_lint(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+fn default_variadic_threshold() -> usize {
+    1
+}
+
+fn default_eval_branching() -> usize {
+    4
+}
+
+fn default_lint() -> bool {
+    true
+}
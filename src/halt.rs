@@ -0,0 +1,83 @@
+use serde::Deserialize;
+
+use crate::lut::Lut;
+
+/// Condition on the outputs of a sequential macro which ends a repeat loop
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HaltCondition {
+    /// Halt once the output is `0`
+    Low(String),
+    /// Halt once the output is `1`
+    High(String),
+    /// Halt once any of the outputs is `1`
+    Any(Vec<String>),
+    /// Halt once the outputs match the given values, such as a state encoding
+    Equals(Vec<(String, bool)>),
+}
+
+impl HaltCondition {
+    /// Outputs which the condition depends on, without duplicates
+    pub fn outputs(&self) -> Vec<&str> {
+        let names = match self {
+            HaltCondition::Low(name) | HaltCondition::High(name) => vec![name.as_str()],
+            HaltCondition::Any(names) => names.iter().map(|name| name.as_str()).collect(),
+            HaltCondition::Equals(pattern) => {
+                pattern.iter().map(|(name, _)| name.as_str()).collect()
+            }
+        };
+
+        let mut outputs = Vec::new();
+        for name in names {
+            if !outputs.contains(&name) {
+                outputs.push(name);
+            }
+        }
+        outputs
+    }
+
+    /// Whether the condition holds, given the values of [`outputs`](HaltCondition::outputs)
+    pub fn halts(&self, values: &[bool]) -> bool {
+        let outputs = self.outputs();
+        let value = |name: &str| values[outputs.iter().position(|output| *output == name).unwrap()];
+
+        match self {
+            HaltCondition::Low(name) => !value(name),
+            HaltCondition::High(name) => value(name),
+            HaltCondition::Any(names) => names.iter().any(|name| value(name)),
+            HaltCondition::Equals(pattern) => {
+                pattern
+                    .iter()
+                    .all(|(name, expected)| value(name) == *expected)
+            }
+        }
+    }
+
+    /// Lut with inputs `I0..` for the [`outputs`](HaltCondition::outputs), whose output `Y` is `1`
+    /// while the loop should continue
+    pub fn lut(&self, name: &str) -> Lut {
+        let num_inputs = self.outputs().len();
+        let input_names = (0..num_inputs)
+            .map(|idx| format!("I{idx}"))
+            .collect::<Vec<_>>();
+        let outputs = (0..1_usize << num_inputs)
+            .map(|idx| {
+                !self.halts(
+                    &(0..num_inputs)
+                        .map(|bit| (idx >> (num_inputs - 1 - bit)) & 1 == 1)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Lut::new(
+            name,
+            &input_names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>(),
+            &["Y"],
+            &outputs,
+        )
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::r#macro::MacroID;
+use crate::scope::global::GlobalScope;
+use crate::state::StateLayout;
+
+/// Simulates a sequential macro by running the preprocessor once per clock cycle, and feeding
+/// the state outputs of each cycle back into the state inputs of the next one. Values are keyed by
+/// port name.
+pub struct Harness {
+    macro_text: String,
+    macro_name: String,
+    input_names: Vec<String>,
+    output_names: Vec<String>,
+    layout: StateLayout,
+}
+
+impl Harness {
+    /// Creates a harness from the macros emitted so far, so the macros should be complete. Every
+    /// input of the macro has to be a module port.
+    pub fn new(global_scope: &GlobalScope, macro_id: MacroID) -> Result<Self, String> {
+        let r#macro = global_scope.get_macro(macro_id);
+        let scope = global_scope.get_scope(r#macro.scope_id);
+
+        let input_names = r#macro
+            .input_names(global_scope)
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("Macro `{}` has inputs which aren't ports", r#macro.name))?;
+        let output_names = scope
+            .local()
+            .output_names
+            .clone()
+            .ok_or_else(|| format!("Macro `{}` has no named outputs", r#macro.name))?;
+
+        Ok(Self {
+            macro_text: global_scope.emit(),
+            macro_name: r#macro.name.clone(),
+            input_names,
+            output_names,
+            layout: StateLayout::new(global_scope, macro_id),
+        })
+    }
+
+    /// Evaluates a single cycle
+    pub fn step(
+        &self,
+        inputs: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, String> {
+        let args = self
+            .input_names
+            .iter()
+            .map(|name| {
+                inputs
+                    .get(name)
+                    .map(String::as_str)
+                    .ok_or_else(|| format!("Missing input `{name}`"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let text = format!(
+            "{}\n{}({})\n",
+            self.macro_text,
+            self.macro_name,
+            args.join(", ")
+        );
+        let output = preprocess(&text)?;
+
+        let values = output.trim().split(',').map(str::trim).collect::<Vec<_>>();
+        if values.len() != self.output_names.len() {
+            return Err(format!(
+                "Expected {} outputs from `{}`, got `{}`",
+                self.output_names.len(),
+                self.macro_name,
+                output.trim()
+            ));
+        }
+
+        Ok(self
+            .output_names
+            .iter()
+            .cloned()
+            .zip(values.into_iter().map(str::to_string))
+            .collect())
+    }
+
+    /// Runs one cycle for each entry of `inputs`, starting from the `{port}.i` values in `state`.
+    /// Returns the outputs of every cycle.
+    pub fn run(
+        &self,
+        state: &HashMap<String, String>,
+        inputs: &[HashMap<String, String>],
+    ) -> Result<Vec<HashMap<String, String>>, String> {
+        let mut state = state.clone();
+        let mut trace = Vec::with_capacity(inputs.len());
+
+        for cycle_inputs in inputs {
+            let mut all_inputs = state.clone();
+            all_inputs.extend(
+                cycle_inputs
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            );
+
+            let outputs = self.step(&all_inputs)?;
+            for (output, input) in self.layout.feedback() {
+                state.insert(
+                    self.input_names[input].clone(),
+                    outputs.get(&self.output_names[output]).unwrap().clone(),
+                );
+            }
+            trace.push(outputs);
+        }
+
+        Ok(trace)
+    }
+}
+
+/// Runs the C preprocessor over `text` and returns its output
+pub fn preprocess(text: &str) -> Result<String, String> {
+    let mut child = Command::new("gcc")
+        .args(["-E", "-P", "-x", "c", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Failed to run the preprocessor: {err}"))?;
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(text.as_bytes())
+        .map_err(|err| format!("Failed to write to the preprocessor: {err}"))?;
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("Failed to run the preprocessor: {err}"))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -11,7 +11,7 @@ use svlint::config::{Config, ConfigOption, ConfigSyntaxRules, ConfigTextRules};
 use svlint::linter::{LintFailed, Linter, TextRuleEvent};
 use walkdir::WalkDir;
 
-pub fn lint_directory<P: AsRef<Path>>(path: P) {
+pub fn lint_directory<P: AsRef<Path>>(path: P) -> Result<(), String> {
     let all_config = Config::new().enable_all();
     let mut linter = Linter::new(Config {
         option: ConfigOption {
@@ -52,9 +52,12 @@ pub fn lint_directory<P: AsRef<Path>>(path: P) {
         .into_iter()
         .filter_map(|entry| entry.ok())
     {
-        if entry.metadata().unwrap().is_file()
-            && ["v", "sv", "vh", "svh"]
-                .contains(&entry.path().extension().unwrap().to_str().unwrap())
+        if entry.metadata().is_ok_and(|metadata| metadata.is_file())
+            && entry
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| ["v", "sv", "vh", "svh"].contains(&extension))
         {
             files.push(entry.path().to_path_buf());
         }
@@ -66,7 +69,8 @@ pub fn lint_directory<P: AsRef<Path>>(path: P) {
     for file in files.iter().map(|file| file.as_path()) {
         let _ = linter.textrules_check(TextRuleEvent::StartOfFile, file, &0);
 
-        let text = fs::read_to_string(file).unwrap();
+        let text = fs::read_to_string(file)
+            .map_err(|err| format!("Failed to read {}: {err}", file.display()))?;
         let mut beg = 0;
 
         let file_id = diag_files.add(file.to_str().unwrap(), text.clone());
@@ -100,8 +104,10 @@ pub fn lint_directory<P: AsRef<Path>>(path: P) {
         }
     }
 
-    if !pass {
-        panic!("Linting failed");
+    if pass {
+        Ok(())
+    } else {
+        Err(format!("Linting {} failed", path.as_ref().display()))
     }
 }
 
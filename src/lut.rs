@@ -1,27 +1,200 @@
 use std::iter::once;
 
-use crate::PREFIX_SEP;
+use crate::Map;
 use crate::expr::Expr;
 use crate::r#macro::{Macro, MacroID};
 use crate::registry::Registry;
 use crate::scope::global::GlobalScope;
 
+const DIGITS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
 #[derive(Clone)]
 pub struct Lut {
-    pub name: &'static str,
+    pub name: String,
+
+    pub input_names: Vec<String>,
+    pub output_names: Vec<String>,
 
-    pub input_names: &'static [&'static str],
-    pub output_name: &'static str,
+    /// Tokens for each value a wire can carry, `0` and `1` for binary luts
+    pub alphabet: Vec<String>,
+    /// Number of values each input can take, counted from the start of the alphabet
+    pub input_radices: Vec<usize>,
 
-    pub outputs: &'static [bool],
+    /// Rows of output values, one for each combination of the inputs. The last input changes
+    /// fastest.
+    pub outputs: Vec<usize>,
+
+    /// Tokens of each input followed by each output, which replace the alphabet for wires with
+    /// scrambled values. Empty if every wire uses the alphabet.
+    pub encodings: Vec<Vec<String>>,
 }
 
 impl Lut {
+    pub fn new(name: &str, input_names: &[&str], output_names: &[&str], outputs: &[bool]) -> Self {
+        Self {
+            name: name.to_string(),
+            input_names: input_names.iter().map(|name| name.to_string()).collect(),
+            output_names: output_names.iter().map(|name| name.to_string()).collect(),
+            alphabet: vec!["0".to_string(), "1".to_string()],
+            input_radices: vec![2; input_names.len()],
+            outputs: outputs.iter().map(|&output| output as usize).collect(),
+            encodings: Vec::new(),
+        }
+    }
+
+    pub fn from_fn<F: Fn(&[usize]) -> Vec<usize>>(
+        name: &str,
+        input_names: &[&str],
+        output_names: &[&str],
+        alphabet: &[&str],
+        input_radices: &[usize],
+        function: F,
+    ) -> Self {
+        assert_eq!(input_names.len(), input_radices.len());
+
+        let mut lut = Self {
+            name: name.to_string(),
+            input_names: input_names.iter().map(|name| name.to_string()).collect(),
+            output_names: output_names.iter().map(|name| name.to_string()).collect(),
+            alphabet: alphabet.iter().map(|token| token.to_string()).collect(),
+            input_radices: input_radices.to_vec(),
+            outputs: Vec::new(),
+            encodings: Vec::new(),
+        };
+
+        for idx in 0..lut.num_rows() {
+            let row = function(&lut.row_inputs(idx));
+            assert_eq!(row.len(), output_names.len());
+            lut.outputs.extend(row);
+        }
+
+        lut
+    }
+
+    /// Digit tokens for luts which operate on numbers in base `radix`
+    pub fn digits(radix: usize) -> Vec<&'static str> {
+        assert!((2..=DIGITS.len()).contains(&radix));
+        (0..radix).map(|digit| &DIGITS[digit..digit + 1]).collect()
+    }
+
+    /// Replaces the alphabet of each input and output with its own tokens
+    pub fn encode(mut self, encodings: Vec<Vec<String>>) -> Self {
+        assert_eq!(
+            encodings.len(),
+            self.input_names.len() + self.output_names.len()
+        );
+        self.encodings = encodings;
+        self
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.alphabet == ["0", "1"]
+    }
+
+    /// Extends a binary lut with an `X` token for unknown values, as computed by `eval_x`
+    pub fn extend_x(&self) -> Self {
+        assert!(self.is_binary());
+
+        Self::from_fn(
+            &self.name,
+            &self
+                .input_names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>(),
+            &self
+                .output_names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>(),
+            &["0", "1", "X"],
+            &vec![3; self.input_names.len()],
+            |inputs| self.eval_x(inputs),
+        )
+    }
+
+    /// Evaluates a binary lut for inputs which may be `X` (2). Outputs are only known if they
+    /// are the same for every possible value of the unknown inputs.
+    pub fn eval_x(&self, inputs: &[usize]) -> Vec<usize> {
+        assert!(self.is_binary());
+
+        let unknown = inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| **input == 2)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+
+        let mut known = self
+            .eval(&inputs.iter().map(|&input| input % 2).collect::<Vec<_>>())
+            .to_vec();
+        for assignment in 1..1_usize << unknown.len() {
+            let mut resolved = inputs.iter().map(|&input| input % 2).collect::<Vec<_>>();
+            for (bit, &idx) in unknown.iter().enumerate() {
+                resolved[idx] = (assignment >> bit) & 1;
+            }
+
+            for (output, &value) in known.iter_mut().zip(self.eval(&resolved)) {
+                if *output != value {
+                    *output = 2;
+                }
+            }
+        }
+
+        known
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[usize]> {
+        self.outputs.chunks(self.output_names.len())
+    }
+
+    pub fn eval(&self, inputs: &[usize]) -> &[usize] {
+        assert_eq!(inputs.len(), self.input_names.len());
+        let idx = inputs
+            .iter()
+            .zip(&self.input_radices)
+            .fold(0, |idx, (&input, &radix)| {
+                assert!(input < radix);
+                idx * radix + input
+            });
+
+        self.rows().nth(idx).unwrap()
+    }
+
     pub fn make_macro(&self, global_scope: &mut GlobalScope) -> MacroID {
-        assert!(!self.outputs.is_empty() && self.outputs.len().is_power_of_two());
+        assert_eq!(self.input_names.len(), self.input_radices.len());
+        assert_eq!(
+            self.num_rows() * self.output_names.len(),
+            self.outputs.len()
+        );
+        assert!(
+            self.input_radices
+                .iter()
+                .all(|&radix| (2..=self.alphabet.len()).contains(&radix))
+        );
+        assert!(
+            self.outputs
+                .iter()
+                .all(|&output| output < self.alphabet.len())
+        );
+
+        let num_inputs = self.input_names.len();
+
+        // Table keys are concatenated tokens, so they must paste into identifiers unambiguously
+        assert!((0..num_inputs + self.output_names.len()).all(|position| {
+            let tokens = self.tokens(position);
+            tokens.len() == self.alphabet.len()
+                && tokens.iter().all(|token| {
+                    token.len() == tokens[0].len()
+                        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                })
+        }));
 
-        let num_inputs = self.outputs.len().ilog2() as usize;
-        assert_eq!(num_inputs, self.input_names.len());
+        if let Some(max_inputs) = global_scope.registry().max_lut_inputs()
+            && num_inputs > max_inputs
+        {
+            return self.make_decomposed_macro(global_scope, max_inputs);
+        }
 
         let mut scope = global_scope.new_scope();
         let vars = self
@@ -29,80 +202,238 @@ impl Lut {
             .iter()
             .map(|name| scope.new_var(name, true, false, None))
             .collect::<Vec<_>>();
-        scope.local().output_names = Some(vec![self.output_name.to_string()]);
+        scope.local().output_names = Some(self.output_names.clone());
 
         let paste_macro = Registry::paste_macro(scope.global, num_inputs + 1, true);
-        let prefix = scope.get_alias(self.name, true);
+        let prefix = scope.get_alias(&self.name, true);
+        let sep = scope.prefix_sep();
 
-        for (idx, &output) in self.outputs.iter().enumerate() {
+        for (idx, row) in self.rows().enumerate() {
             scope.define(
-                format!("{prefix}{PREFIX_SEP}{:0len$b}", idx, len = num_inputs),
-                if output {
-                    "1".to_string()
-                } else {
-                    "0".to_string()
-                },
+                format!("{prefix}{sep}{}", self.row_key(idx, &self.input_radices)),
+                self.row_text(row),
             );
         }
 
         scope.new_macro(Macro {
             scope_id: scope.id,
-            name: scope.get_alias(self.name, false),
+            name: scope.get_alias(&self.name, false),
             expr: Expr::Call {
                 r#macro: Box::new(Expr::Macro(paste_macro)),
-                args: once(Expr::Text(format!("{prefix}{PREFIX_SEP}")))
+                args: once(Expr::Text(format!("{prefix}{sep}")))
                     .chain(vars.iter().map(|&var| Expr::Var(var)))
                     .collect(),
             },
             inputs: vars,
             variadicified_vars: None,
             calling_split: None,
-            doc_name: Some(self.name.to_string()),
+            doc_name: Some(self.name.clone()),
         })
     }
 
-    pub fn not() -> Self {
-        Self {
-            name: "$_NOT_",
-            input_names: &["A"],
-            output_name: "Y",
-            outputs: &[true, false],
+    /// Shannon decomposition over the leading inputs. A selector table maps the leading inputs to
+    /// the name of a cofactor macro, which is then called with the trailing inputs. Constant and
+    /// duplicate cofactors share a macro, so they don't need tables of their own.
+    fn make_decomposed_macro(&self, global_scope: &mut GlobalScope, max_inputs: usize) -> MacroID {
+        assert_ne!(max_inputs, 0);
+
+        let num_inputs = self.input_names.len();
+        let num_head = max_inputs.min(num_inputs - max_inputs);
+        let num_outputs = self.output_names.len();
+        let cofactor_rows = self.input_radices[num_head..].iter().product::<usize>();
+
+        let mut cofactor_macros = Map::new();
+        let mut selector_targets = Vec::new();
+        for cofactor in self.outputs.chunks(num_outputs * cofactor_rows) {
+            let first_row = &cofactor[..num_outputs];
+            let macro_id = if cofactor.chunks(num_outputs).all(|row| row == first_row) {
+                Registry::const_macro(global_scope, &self.row_text(first_row))
+            } else if let Some(&macro_id) = cofactor_macros.get(cofactor) {
+                macro_id
+            } else {
+                let lut = Lut {
+                    name: format!("{}_COF{}", self.name, cofactor_macros.len()),
+                    input_names: self.input_names[num_head..].to_vec(),
+                    output_names: self.output_names.clone(),
+                    alphabet: self.alphabet.clone(),
+                    input_radices: self.input_radices[num_head..].to_vec(),
+                    outputs: cofactor.to_vec(),
+                    encodings: if self.encodings.is_empty() {
+                        Vec::new()
+                    } else {
+                        self.encodings[num_head..].to_vec()
+                    },
+                };
+                let macro_id = lut.make_macro(global_scope);
+                cofactor_macros.insert(cofactor.to_vec(), macro_id);
+                macro_id
+            };
+
+            selector_targets.push(global_scope.get_macro(macro_id).name.clone());
         }
+
+        let mut scope = global_scope.new_scope();
+        let vars = self
+            .input_names
+            .iter()
+            .map(|name| scope.new_var(name, true, false, None))
+            .collect::<Vec<_>>();
+        scope.local().output_names = Some(self.output_names.clone());
+
+        let paste_macro = Registry::paste_macro(scope.global, num_head + 1, true);
+        let prefix = scope.get_alias(&self.name, true);
+        let sep = scope.prefix_sep();
+
+        for (idx, target) in selector_targets.into_iter().enumerate() {
+            scope.define(
+                format!(
+                    "{prefix}{sep}{}",
+                    self.row_key(idx, &self.input_radices[..num_head])
+                ),
+                target,
+            );
+        }
+
+        scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&self.name, false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Call {
+                    r#macro: Box::new(Expr::Macro(paste_macro)),
+                    args: once(Expr::Text(format!("{prefix}{sep}")))
+                        .chain(vars[..num_head].iter().map(|&var| Expr::Var(var)))
+                        .collect(),
+                }),
+                args: vars[num_head..].iter().map(|&var| Expr::Var(var)).collect(),
+            },
+            inputs: vars,
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: Some(self.name.clone()),
+        })
     }
 
-    pub fn or() -> Self {
-        Self {
-            name: "$_OR_",
-            input_names: &["A", "B"],
-            output_name: "Y",
-            outputs: &[false, true, true, true],
+    fn num_rows(&self) -> usize {
+        self.input_radices.iter().product()
+    }
+
+    fn row_inputs(&self, mut idx: usize) -> Vec<usize> {
+        let mut inputs = vec![0; self.input_radices.len()];
+        for (input, &radix) in inputs.iter_mut().zip(&self.input_radices).rev() {
+            *input = idx % radix;
+            idx /= radix;
         }
+
+        inputs
     }
 
-    pub fn and() -> Self {
-        Self {
-            name: "$_AND_",
-            input_names: &["A", "B"],
-            output_name: "Y",
-            outputs: &[false, false, false, true],
+    fn row_key(&self, mut idx: usize, radices: &[usize]) -> String {
+        let mut tokens = Vec::new();
+        for (position, &radix) in radices.iter().enumerate().rev() {
+            tokens.push(self.tokens(position)[idx % radix].as_str());
+            idx /= radix;
         }
+
+        tokens.into_iter().rev().collect()
+    }
+
+    fn row_text(&self, row: &[usize]) -> String {
+        row.iter()
+            .enumerate()
+            .map(|(idx, &output)| self.tokens(self.input_names.len() + idx)[output].as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Tokens of an input, or of an output after the inputs
+    fn tokens(&self, position: usize) -> &[String] {
+        self.encodings.get(position).unwrap_or(&self.alphabet)
+    }
+
+    pub fn not() -> Self {
+        Self::new("$_NOT_", &["A"], &["Y"], &[true, false])
+    }
+
+    pub fn or() -> Self {
+        Self::new("$_OR_", &["A", "B"], &["Y"], &[false, true, true, true])
+    }
+
+    pub fn and() -> Self {
+        Self::new("$_AND_", &["A", "B"], &["Y"], &[false, false, false, true])
     }
 
     pub fn xor() -> Self {
-        Self {
-            name: "$_XOR_",
-            input_names: &["A", "B"],
-            output_name: "Y",
-            outputs: &[false, true, true, false],
-        }
+        Self::new("$_XOR_", &["A", "B"], &["Y"], &[false, true, true, false])
     }
 
     pub fn mux() -> Self {
-        Self {
-            name: "$_MUX_",
-            input_names: &["S", "A", "B"],
-            output_name: "Y",
-            outputs: &[false, false, true, true, false, true, false, true],
-        }
+        Self::new(
+            "$_MUX_",
+            &["S", "A", "B"],
+            &["Y"],
+            &[false, false, true, true, false, true, false, true],
+        )
+    }
+
+    /// Adds two digits and a binary carry
+    pub fn digit_adder(radix: usize) -> Self {
+        Self::from_fn(
+            &format!("$_ADD{radix}_"),
+            &["A", "B", "C"],
+            &["S", "CO"],
+            &Self::digits(radix),
+            &[radix, radix, 2],
+            |inputs| {
+                let sum = inputs.iter().sum::<usize>();
+                vec![sum % radix, sum / radix]
+            },
+        )
+    }
+
+    /// Packs bits, most significant first, into a digit. Values which don't fit wrap around.
+    pub fn bits_to_digit(radix: usize) -> Self {
+        let num_bits = radix.next_power_of_two().ilog2() as usize;
+        let input_names = (0..num_bits)
+            .rev()
+            .map(|bit| format!("B{bit}"))
+            .collect::<Vec<_>>();
+
+        Self::from_fn(
+            &format!("$_BITS_TO_DIGIT{radix}_"),
+            &input_names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>(),
+            &["D"],
+            &Self::digits(radix),
+            &vec![2; num_bits],
+            |inputs| vec![inputs.iter().fold(0, |acc, &bit| acc * 2 + bit) % radix],
+        )
+    }
+
+    /// Unpacks a digit into bits, most significant first
+    pub fn digit_to_bits(radix: usize) -> Self {
+        let num_bits = radix.next_power_of_two().ilog2() as usize;
+        let output_names = (0..num_bits)
+            .rev()
+            .map(|bit| format!("B{bit}"))
+            .collect::<Vec<_>>();
+
+        Self::from_fn(
+            &format!("$_DIGIT_TO_BITS{radix}_"),
+            &["D"],
+            &output_names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>(),
+            &Self::digits(radix),
+            &[radix],
+            |inputs| {
+                (0..num_bits)
+                    .rev()
+                    .map(|bit| (inputs[0] >> bit) & 1)
+                    .collect()
+            },
+        )
     }
 }
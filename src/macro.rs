@@ -32,6 +32,22 @@ impl Macro {
         self.inputs.iter().position(|input| input == var_id)
     }
 
+    /// Names of the inputs which are mapped from module ports, in the order of the macro inputs
+    pub fn input_names(&self, global_scope: &GlobalScope) -> Vec<Option<String>> {
+        let scope = global_scope.get_scope(self.scope_id);
+        self.inputs
+            .iter()
+            .map(|input| {
+                scope
+                    .local()
+                    .input_map
+                    .iter()
+                    .find(|(_, var_id)| *var_id == input)
+                    .map(|(name, _)| name.clone())
+            })
+            .collect()
+    }
+
     pub fn check_inputs(
         &self,
         indices: Vec<usize>,
@@ -263,7 +279,7 @@ impl Macro {
     pub fn emit(&self, global_scope: &GlobalScope) -> String {
         let scope = global_scope.get_scope(self.scope_id);
         let docs = if let Some(doc_name) = &self.doc_name {
-            if cfg!(feature = "obfuscate") {
+            if global_scope.obfuscation().enabled {
                 String::new()
             } else {
                 format!(
@@ -315,7 +331,7 @@ impl Macro {
             let variadic_expr_text =
                 expr_text.replace(&span, "__VA_ARGS__".magenta().to_string().as_str());
 
-            if cfg!(not(feature = "obfuscate"))
+            if !global_scope.obfuscation().enabled
                 || strip_str(&variadic_inputs).len() + strip_str(&variadic_expr_text).len()
                     < strip_str(&inputs).len() + strip_str(&expr_text).len()
             {
@@ -1,19 +1,219 @@
-use vlogpp::lint::lint_directory;
-use vlogpp::netlist::Netlist;
-use vlogpp::registry::Registry;
-use vlogpp::scope::global::GlobalScope;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-fn main() {
-    lint_directory("circuits");
+use clap::{Parser, Subcommand};
+use vlogpp::config::{Config, RepeatConfig};
+use vlogpp::halt::HaltCondition;
+use vlogpp::obfuscation::ObfuscationKey;
 
-    let netlist = Netlist::new("circuits/adder.sv", true, &[]);
-    let registry = Registry::default().add_netlist(netlist);
+/// Compiles SystemVerilog circuits into C preprocessor macros
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    let mut global_scope = GlobalScope::new(registry);
-    Registry::module(&mut global_scope, "adder").unwrap();
-    global_scope.variadicify_macros(1);
+    /// SystemVerilog source files, instead of a config file
+    inputs: Vec<PathBuf>,
 
-    // Registry::repeat_macro(&mut global_scope, top, "cont");
-    // Registry::eval_multiplier(&mut global_scope, 5);
-    println!("{global_scope}");
+    /// Config file to run, `vlogpp.toml` if no source files are given
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = [
+            "inputs", "top", "chparams", "output", "variadic_threshold", "cont", "eval_branching"
+        ]
+    )]
+    config: Option<PathBuf>,
+
+    /// Module to emit, instead of the top modules found by yosys
+    #[arg(short, long)]
+    top: Option<String>,
+
+    /// Parameter override, can be repeated
+    #[arg(short = 'p', long = "chparam", value_name = "MODULE.PARAM=VALUE")]
+    chparams: Vec<String>,
+
+    /// File to write the macros to, instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Shortest run of arguments which is replaced by `__VA_ARGS__`, or 0 to keep all arguments
+    /// [default: 1]
+    #[arg(long)]
+    variadic_threshold: Option<usize>,
+
+    /// Also emit a `RUN_` macro which steps each top module until this output is `0`
+    #[arg(long, value_name = "OUTPUT")]
+    cont: Option<String>,
+
+    /// Most cycles a `RUN_` macro can run for, after which it leaves an unexpanded call
+    #[arg(long, default_value_t = 1000, requires = "cont")]
+    max_cycles: usize,
+
+    /// Number of times each `EVAL` level nests the level below it [default: 4]
+    #[arg(long)]
+    eval_branching: Option<usize>,
+
+    /// Skip linting the source files
+    #[arg(long)]
+    no_lint: bool,
+
+    /// Show the synthesized netlist in a viewer
+    #[arg(long)]
+    show_netlist: bool,
+
+    /// Obfuscate the output, which is the default unless the config file disables it
+    #[arg(long, conflicts_with = "no_obfuscate")]
+    obfuscate: bool,
+
+    /// Emit readable output
+    #[arg(long)]
+    no_obfuscate: bool,
+
+    /// Seed for the obfuscation and logic rewrites, which makes the output reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Chance of each equivalent rewrite of the gates, from 0 for none to 1
+    #[arg(long, value_name = "CHANCE")]
+    logic_intensity: Option<f64>,
+
+    /// Give the wires inside modules random tokens instead of `0` and `1`
+    #[arg(long, conflicts_with = "no_obfuscate")]
+    scramble_wires: bool,
+
+    /// File to write the key for `vlogpp deobfuscate` to
+    #[arg(long, conflicts_with = "no_obfuscate")]
+    key: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replaces the names from an obfuscated header in a header, preprocessor output or error
+    /// message
+    Deobfuscate {
+        /// Key file written alongside the header
+        #[arg(short, long)]
+        key: PathBuf,
+
+        /// File to rewrite, instead of stdin
+        input: Option<PathBuf>,
+
+        /// File to write the result to, instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    if let Some(Command::Deobfuscate { key, input, output }) = args.command {
+        return deobfuscate(key, input, output);
+    }
+
+    let mut config = if args.inputs.is_empty() {
+        Config::from_file(args.config.unwrap_or("vlogpp.toml".into()))?
+    } else {
+        config_from_args(&args)?
+    };
+    config.lint.enabled &= !args.no_lint;
+    config.show_netlist = args.show_netlist;
+    if args.obfuscate {
+        config.obfuscation.enabled = true;
+    }
+    if args.no_obfuscate {
+        config.obfuscation.enabled = false;
+    }
+    if let Some(seed) = args.seed {
+        config.obfuscation.seed = Some(seed);
+    }
+    if let Some(intensity) = args.logic_intensity {
+        config.obfuscation.logic_intensity = intensity;
+    }
+    config.obfuscation.scramble_wires |= args.scramble_wires;
+    if let Some(key) = args.key {
+        config.obfuscation.key = Some(key);
+    }
+
+    config.write(&config.build()?)
+}
+
+fn deobfuscate(
+    key: PathBuf,
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+) -> Result<(), String> {
+    let key = ObfuscationKey::from_file(key)?;
+    let text = match &input {
+        Some(path) => {
+            fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))?
+        }
+        None => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|err| format!("Failed to read stdin: {err}"))?;
+            text
+        }
+    };
+
+    let text = key.deobfuscate(&text);
+    match &output {
+        Some(path) => {
+            fs::write(path, text)
+                .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+        }
+        None => {
+            print!("{text}");
+            Ok(())
+        }
+    }
+}
+
+fn config_from_args(args: &Args) -> Result<Config, String> {
+    let mut config = Config::new(args.inputs.clone());
+    config.tops.extend(args.top.clone());
+    config.output.path = args.output.clone();
+    config.variadic_threshold = args.variadic_threshold.unwrap_or(config.variadic_threshold);
+    config.eval_branching = args.eval_branching.unwrap_or(config.eval_branching);
+
+    for chparam in &args.chparams {
+        let (module, param, value) = chparam
+            .split_once('=')
+            .and_then(|(target, value)| {
+                let (module, param) = target.rsplit_once('.')?;
+                Some((module, param, value))
+            })
+            .ok_or_else(|| {
+                format!("Invalid parameter override `{chparam}`, expected MODULE.PARAM=VALUE")
+            })?;
+        config
+            .params
+            .entry(module.to_string())
+            .or_default()
+            .insert(param.to_string(), toml::Value::String(value.to_string()));
+    }
+
+    if let Some(cont) = &args.cont {
+        config.repeat.push(RepeatConfig {
+            module: None,
+            halt: HaltCondition::Low(cont.clone()),
+            max_cycles: Some(args.max_cycles),
+        });
+    }
+
+    Ok(config)
 }
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use crate::lut::Lut;
+use crate::netlist::{Cell, Module, PortDir, Wire};
+use crate::{Map, Set};
+
+const CUTS_PER_WIRE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MappingGoal {
+    /// Minimize the number of nested lookups between the inputs and outputs
+    Depth,
+    /// Minimize the total number of lookups
+    Lookups,
+}
+
+#[derive(Clone, Debug)]
+struct Cut {
+    leaves: Vec<Wire>,
+    depth: usize,
+    area_flow: f64,
+}
+
+impl Cut {
+    fn trivial(wire: Wire, depth: usize, area_flow: f64) -> Self {
+        Self {
+            leaves: vec![wire],
+            depth,
+            area_flow,
+        }
+    }
+
+    fn cost(&self, goal: MappingGoal) -> (f64, f64, usize) {
+        match goal {
+            MappingGoal::Depth => (self.depth as f64, self.area_flow, self.leaves.len()),
+            MappingGoal::Lookups => (self.area_flow, self.depth as f64, self.leaves.len()),
+        }
+    }
+}
+
+/// Collapses cones of lut cells into synthesized luts with up to `max_inputs` inputs. Cells which
+/// aren't binary luts with a single output are kept as they are and act as boundaries for the
+/// cones. Returns the mapped module and the luts which it uses that don't exist in `luts` yet.
+pub fn map_module(
+    module: &Module,
+    luts: &Map<String, Lut>,
+    max_inputs: usize,
+    goal: MappingGoal,
+) -> (Module, Vec<Lut>) {
+    assert!(max_inputs > 1);
+
+    let is_lut_cell = |cell: &Cell| {
+        luts.get(&cell.kind)
+            .is_some_and(|lut| lut.is_binary() && lut.output_names.len() == 1)
+    };
+
+    // Find the lut cells driving each wire
+    let mut drivers = HashMap::new();
+    let mut fanouts = HashMap::new();
+    for (cell_name, cell) in &module.cells {
+        if is_lut_cell(cell)
+            && let Some((_, wire)) = cell.output_connections().next()
+        {
+            drivers.insert(wire, cell_name.as_str());
+        }
+
+        for (_, wire) in cell.input_connections() {
+            *fanouts.entry(wire).or_insert(0_usize) += 1;
+        }
+    }
+
+    // Wires needed outside of lut cones
+    let mut roots = module
+        .output_ports()
+        .map(|(_, port)| port.wire)
+        .collect::<Vec<_>>();
+    for cell in module.cells.values() {
+        if !is_lut_cell(cell) {
+            roots.extend(cell.input_connections().map(|(_, wire)| wire));
+        }
+    }
+    for &wire in &roots {
+        *fanouts.entry(wire).or_insert(0) += 1;
+    }
+
+    // Enumerate cuts in topological order
+    let mut cuts: HashMap<Wire, Vec<Cut>> = HashMap::new();
+    let mut order = Vec::new();
+    let mut visited = Set::new();
+    for &root in &roots {
+        topo_visit(root, module, &drivers, &mut visited, &mut order);
+    }
+
+    for wire in order {
+        let Some(cell_name) = drivers.get(&wire) else {
+            // Constants are folded into the tables instead of taking up inputs
+            let cut = if let Wire::Const(_) = wire {
+                Cut {
+                    leaves: Vec::new(),
+                    depth: 0,
+                    area_flow: 0.0,
+                }
+            } else {
+                Cut::trivial(wire, 0, 0.0)
+            };
+            cuts.insert(wire, vec![cut]);
+            continue;
+        };
+
+        let cell = module.cells.get(*cell_name).unwrap();
+        let mut merged: Vec<Vec<Wire>> = vec![Vec::new()];
+        for (_, input) in cell.input_connections() {
+            let mut next = Vec::new();
+            for partial in &merged {
+                for cut in cuts.get(&input).unwrap() {
+                    let mut leaves = partial.clone();
+                    for leaf in &cut.leaves {
+                        if !leaves.contains(leaf) {
+                            leaves.push(*leaf);
+                        }
+                    }
+
+                    if leaves.len() <= max_inputs {
+                        leaves.sort_by_key(wire_key);
+                        if !next.contains(&leaves) {
+                            next.push(leaves);
+                        }
+                    }
+                }
+            }
+            merged = next;
+        }
+
+        // Cells wider than the limit can only be implemented by themselves
+        if merged.is_empty() {
+            let mut leaves = Vec::new();
+            for (_, input) in cell.input_connections() {
+                if !matches!(input, Wire::Const(_)) && !leaves.contains(&input) {
+                    leaves.push(input);
+                }
+            }
+            leaves.sort_by_key(wire_key);
+            merged.push(leaves);
+        }
+
+        let arrival = |leaf: &Wire| cuts.get(leaf).unwrap().first().unwrap().depth;
+        let flow = |leaf: &Wire| {
+            cuts.get(leaf).unwrap().first().unwrap().area_flow
+                / *fanouts.get(leaf).unwrap_or(&1).max(&1) as f64
+        };
+
+        let mut wire_cuts = merged
+            .into_iter()
+            .map(|leaves| {
+                Cut {
+                    depth: 1 + leaves.iter().map(arrival).max().unwrap_or_default(),
+                    area_flow: 1.0 + leaves.iter().map(flow).sum::<f64>(),
+                    leaves,
+                }
+            })
+            .collect::<Vec<_>>();
+        wire_cuts.sort_by(|a, b| a.cost(goal).partial_cmp(&b.cost(goal)).unwrap());
+        wire_cuts.truncate(CUTS_PER_WIRE);
+
+        let best = wire_cuts.first().unwrap();
+        let trivial = Cut::trivial(wire, best.depth, best.area_flow);
+        wire_cuts.push(trivial);
+        cuts.insert(wire, wire_cuts);
+    }
+
+    // Select cuts starting from the roots
+    let mut selected = Map::new();
+    let mut stack = roots.clone();
+    while let Some(wire) = stack.pop() {
+        if !drivers.contains_key(&wire) || selected.contains_key(&wire) {
+            continue;
+        }
+
+        let cut = cuts.get(&wire).unwrap().first().unwrap().clone();
+        stack.extend(cut.leaves.iter().copied());
+        selected.insert(wire, cut);
+    }
+
+    // Build the mapped module
+    let mut mapped = Module {
+        attributes: module.attributes.clone(),
+        ports: module.ports.clone(),
+        cells: Map::new(),
+        netnames: module.netnames.clone(),
+        reset_values: module.reset_values.clone(),
+    };
+    for (cell_name, cell) in &module.cells {
+        if !is_lut_cell(cell) {
+            mapped.cells.insert(cell_name.clone(), cell.clone());
+        }
+    }
+
+    let mut new_luts: Vec<Lut> = Vec::new();
+    let mut constants = HashMap::new();
+    for (wire, cut) in selected {
+        let cell_name = *drivers.get(&wire).unwrap();
+        let cell = module.cells.get(cell_name).unwrap();
+
+        // Keep cells which are already optimal as they are
+        if cell.input_connections().count() == cut.leaves.len()
+            && cell
+                .input_connections()
+                .all(|(_, input)| cut.leaves.contains(&input))
+        {
+            mapped.cells.insert(cell_name.to_string(), cell.clone());
+            continue;
+        }
+
+        // Cones without leaves are constants, which their consumers use directly
+        if cut.leaves.is_empty() {
+            let value = evaluate(wire, module, luts, &drivers, &mut HashMap::new());
+            constants.insert(wire, Wire::Const(value == 1));
+            continue;
+        }
+
+        let outputs = (0..1_usize << cut.leaves.len())
+            .map(|idx| {
+                let mut values = cut
+                    .leaves
+                    .iter()
+                    .enumerate()
+                    .map(|(leaf_idx, leaf)| (*leaf, (idx >> (cut.leaves.len() - 1 - leaf_idx)) & 1))
+                    .collect::<HashMap<_, _>>();
+
+                // Undriven bits have no driver, so they are leaves which the table treats as
+                // ordinary binary inputs. Under `propagate_x`, the collapsed cone therefore
+                // resolves cases like `x ^ x` to a known value, where gate-level evaluation
+                // gives `X`.
+                evaluate(wire, module, luts, &drivers, &mut values) == 1
+            })
+            .collect::<Vec<_>>();
+
+        let lut_name = format!(
+            "$_LUT{}_{}_",
+            cut.leaves.len(),
+            outputs
+                .chunks(4)
+                .map(|nibble| {
+                    let value = nibble
+                        .iter()
+                        .fold(0, |acc, &output| (acc << 1) | output as u32);
+                    char::from_digit(value, 16).unwrap()
+                })
+                .collect::<String>()
+        );
+        let input_names = (0..cut.leaves.len())
+            .map(|idx| format!("I{idx}"))
+            .collect::<Vec<_>>();
+
+        if !luts.contains_key(&lut_name) && !new_luts.iter().any(|lut| lut.name == lut_name) {
+            new_luts.push(Lut::new(
+                &lut_name,
+                &input_names
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>(),
+                &["Y"],
+                &outputs,
+            ));
+        }
+
+        mapped.cells.insert(
+            cell_name.to_string(),
+            Cell {
+                kind: lut_name,
+                port_dirs: input_names
+                    .iter()
+                    .map(|name| (name.clone(), PortDir::Input))
+                    .chain(std::iter::once(("Y".to_string(), PortDir::Output)))
+                    .collect(),
+                connections: input_names
+                    .into_iter()
+                    .zip(cut.leaves.iter().copied())
+                    .chain(std::iter::once(("Y".to_string(), wire)))
+                    .collect(),
+            },
+        );
+    }
+
+    for wire in mapped
+        .cells
+        .values_mut()
+        .flat_map(|cell| cell.connections.values_mut())
+        .chain(mapped.ports.values_mut().map(|port| &mut port.wire))
+    {
+        if let Some(&constant) = constants.get(wire) {
+            *wire = constant;
+        }
+    }
+
+    (mapped, new_luts)
+}
+
+fn topo_visit(
+    wire: Wire,
+    module: &Module,
+    drivers: &HashMap<Wire, &str>,
+    visited: &mut Set<Wire>,
+    order: &mut Vec<Wire>,
+) {
+    if !visited.insert(wire) {
+        return;
+    }
+
+    if let Some(cell_name) = drivers.get(&wire) {
+        for (_, input) in module.cells.get(*cell_name).unwrap().input_connections() {
+            topo_visit(input, module, drivers, visited, order);
+        }
+    }
+
+    order.push(wire);
+}
+
+fn evaluate(
+    wire: Wire,
+    module: &Module,
+    luts: &Map<String, Lut>,
+    drivers: &HashMap<Wire, &str>,
+    values: &mut HashMap<Wire, usize>,
+) -> usize {
+    if let Some(&value) = values.get(&wire) {
+        return value;
+    }
+
+    let value = match wire {
+        Wire::Const(constant) => constant as usize,
+        Wire::Undef => 2,
+        Wire::Wire(_) => {
+            let cell = module.cells.get(*drivers.get(&wire).unwrap()).unwrap();
+            let lut = luts.get(&cell.kind).unwrap();
+            let inputs = lut
+                .input_names
+                .iter()
+                .map(|name| {
+                    let input = *cell.connections.get(name).unwrap();
+                    evaluate(input, module, luts, drivers, values)
+                })
+                .collect::<Vec<_>>();
+            lut.eval_x(&inputs)[0]
+        }
+    };
+
+    values.insert(wire, value);
+    value
+}
+
+fn wire_key(wire: &Wire) -> (bool, usize) {
+    match wire {
+        Wire::Wire(id) => (false, *id),
+        Wire::Const(constant) => (true, *constant as usize),
+        Wire::Undef => (true, 2),
+    }
+}
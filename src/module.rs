@@ -75,13 +75,32 @@ pub fn create_module(name: &str, module: &Module, global_scope: &mut GlobalScope
 
     let cell_topo = topo_sort_cells(&module.cells);
 
-    let mut wire_infos = consumer_counts(module);
+    let undef_text = if scope.registry().x_propagation() {
+        "X"
+    } else {
+        "0"
+    };
+    let mut wire_infos = consumer_counts(module, undef_text);
     let mut var_wires = create_inputs(&mut wire_infos, module, &mut scope);
+    let encodings = if scope.global.obfuscation().scramble_wires {
+        wire_encodings(module, scope.global)
+    } else {
+        Map::new()
+    };
 
     for &cell_idx in &cell_topo {
         let (cell_name, cell) = module.cells.iter().nth(cell_idx).unwrap();
-        let call_macro = Registry::module(scope.global, &cell.kind)
-            .unwrap_or_else(|| panic!("Unknown cell type `{}`", &cell.kind));
+        let cell_encodings = cell
+            .connections
+            .iter()
+            .filter_map(|(port, wire)| Some((port.clone(), encodings.get(wire)?.clone())))
+            .collect::<Map<_, _>>();
+        let call_macro = if cell_encodings.is_empty() {
+            Registry::module(scope.global, &cell.kind)
+        } else {
+            Registry::encoded_lut(scope.global, &cell.kind, &cell_encodings)
+        }
+        .unwrap_or_else(|| panic!("Unknown cell type `{}`", &cell.kind));
         let call_macro_scope = scope.get_macro(call_macro).scope_id;
 
         let mut input_wires = cell
@@ -158,8 +177,12 @@ pub fn create_module(name: &str, module: &Module, global_scope: &mut GlobalScope
                 .map(|_| scope.new_var("bt", false, false, Some(bundle_var)))
                 .collect::<Vec<_>>();
             let bundle_expr = Some((bundle_var, expanded_vars.clone()));
+            scope
+                .local()
+                .set_origin(bundle_var, format!("outputs of cell `{cell_name}`"));
 
             for ((wire, _), &temp) in output_wires.iter().zip(expanded_vars.iter()) {
+                scope.local().set_origin(temp, wire_origin(module, *wire));
                 let wire_info = wire_infos.get_mut(wire).unwrap();
                 wire_info.expr = Some(expr.clone());
                 wire_info.split_idx_lb = Some(split_idx_lb);
@@ -183,6 +206,7 @@ pub fn create_module(name: &str, module: &Module, global_scope: &mut GlobalScope
 
             if total_consumers > 1 {
                 let var_id = scope.new_var("t", false, false, None);
+                scope.local().set_origin(var_id, wire_origin(module, *wire));
                 var_wires.insert(var_id, *wire);
                 wire_info.downstream_expr = Some(Expr::Var(var_id));
                 wire_info.split_delta = Some(1);
@@ -270,6 +294,47 @@ pub fn create_module(name: &str, module: &Module, global_scope: &mut GlobalScope
     *ids.first().unwrap()
 }
 
+/// Random encodings for the wires which only connect luts. Ports and constants keep the
+/// alphabet, so the tables of the cells driving the outputs convert back to it.
+fn wire_encodings(module: &Module, global_scope: &GlobalScope) -> Map<Wire, Vec<String>> {
+    let mut alphabets = Map::new();
+    for cell in module.cells.values() {
+        let alphabet = global_scope.registry().scrambled_alphabet(&cell.kind);
+        for &wire in cell.connections.values() {
+            alphabets
+                .entry(wire)
+                .and_modify(|existing: &mut Option<Vec<String>>| {
+                    if *existing != alphabet {
+                        *existing = None;
+                    }
+                })
+                .or_insert_with(|| alphabet.clone());
+        }
+    }
+    for port in module.ports.values() {
+        alphabets.insert(port.wire, None);
+    }
+
+    alphabets
+        .into_iter()
+        .filter_map(|(wire, alphabet)| {
+            let Wire::Wire(_) = wire else {
+                return None;
+            };
+            let encoding = global_scope.wire_encoding(&alphabet?, &wire_origin(module, wire));
+            Some((wire, encoding))
+        })
+        .collect()
+}
+
+fn wire_origin(module: &Module, wire: Wire) -> String {
+    match (module.wire_name(wire), wire) {
+        (Some(name), _) => format!("wire `{name}`"),
+        (None, Wire::Wire(id)) => format!("wire {id}"),
+        (None, _) => format!("{wire:?}"),
+    }
+}
+
 fn topo_sort_cells(cells: &Map<String, Cell>) -> Vec<usize> {
     let mut children = vec![Vec::new(); cells.len()];
     let mut incoming = vec![0_usize; cells.len()];
@@ -317,7 +382,7 @@ fn topo_sort_cells(cells: &Map<String, Cell>) -> Vec<usize> {
     topo
 }
 
-fn consumer_counts(module: &Module) -> Map<Wire, WireInfo> {
+fn consumer_counts(module: &Module, undef_text: &str) -> Map<Wire, WireInfo> {
     let mut consumer_counts = Map::new();
     for producer in module.output_ports().map(|(_name, port)| port.wire).chain(
         module
@@ -347,18 +412,17 @@ fn consumer_counts(module: &Module) -> Map<Wire, WireInfo> {
                     input_wires: Set::new(),
                 };
 
-                match wire {
-                    Wire::Const(constant) => {
-                        wire_info.expr = Some(Expr::Text(if constant {
-                            "1".to_string()
-                        } else {
-                            "0".to_string()
-                        }));
-                        wire_info.split_idx_lb = Some(0);
-                        wire_info.split_idx_ub = Some(0); // TODO: Not this
-                        wire_info.split_delta = Some(0);
-                    }
-                    Wire::Wire(_) => {}
+                let const_text = match wire {
+                    Wire::Const(constant) => Some(if constant { "1" } else { "0" }),
+                    Wire::Undef => Some(undef_text),
+                    Wire::Wire(_) => None,
+                };
+
+                if let Some(const_text) = const_text {
+                    wire_info.expr = Some(Expr::Text(const_text.to_string()));
+                    wire_info.split_idx_lb = Some(0);
+                    wire_info.split_idx_ub = Some(0); // TODO: Not this
+                    wire_info.split_delta = Some(0);
                 }
 
                 (wire, wire_info)
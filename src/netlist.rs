@@ -16,41 +16,66 @@ pub struct Netlist {
 }
 
 impl Netlist {
-    pub fn new<P: AsRef<Path>>(file: P, display: bool, top_params: &[(&str, &str, &str)]) -> Self {
+    pub fn new<P: AsRef<Path>>(
+        file: P,
+        display: bool,
+        top_params: &[(&str, &str, &str)],
+    ) -> Result<Self, String> {
+        Netlist::from_files(&[file], display, top_params)
+    }
+
+    /// Synthesizes the modules of several source files into one netlist, so that modules can
+    /// instantiate modules from other files
+    pub fn from_files<P: AsRef<Path>>(
+        files: &[P],
+        display: bool,
+        top_params: &[(&str, &str, &str)],
+    ) -> Result<Self, String> {
         let params = top_params
             .iter()
             .map(|(k, v, module)| format!("chparam -set {k} {v} {module};"))
             .collect::<Vec<_>>()
             .join("");
+        let files = files
+            .iter()
+            .map(|file| file.as_ref().display().to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
 
         let commands = indoc::formatdoc! {"
-            read_verilog -sv {};
+            read_verilog -sv {files};
             {params}
             hierarchy -check -auto-top;
             proc;; memory;; fsm;; wreduce;; opt -full;;
             techmap;; opt -full;;
             splitnets -ports;; expose -dff -cut;; opt -full;;
             clean -purge;
-            write_json design.json", file.as_ref().display()};
+            write_json design.json"};
 
         let status = Command::new("yosys")
             .arg("-p")
             .arg(commands)
             .status()
-            .expect("Yosys netlist generation failed");
-        assert!(status.success());
+            .map_err(|err| format!("Failed to run yosys: {err}"))?;
+        if !status.success() {
+            return Err(format!("Yosys failed to synthesize {files}"));
+        }
 
-        let buffer = BufReader::new(File::open("design.json").unwrap());
-        let mut netlist: Netlist = serde_json::from_reader(buffer).unwrap();
-        netlist.remove_flip_flops();
+        let buffer = BufReader::new(
+            File::open("design.json")
+                .map_err(|err| format!("Failed to open design.json: {err}"))?,
+        );
+        let mut netlist: Netlist = serde_json::from_reader(buffer)
+            .map_err(|err| format!("Failed to parse design.json: {err}"))?;
+        netlist.remove_flip_flops()?;
 
         if display {
             netlist.show();
         }
-        netlist
+        Ok(netlist)
     }
 
-    fn remove_flip_flops(&mut self) {
+    fn remove_flip_flops(&mut self) -> Result<(), String> {
         let mut callers = HashMap::new();
         for (module_name, module) in self.modules.iter() {
             for cell in module.cells.values() {
@@ -95,11 +120,82 @@ impl Netlist {
 
             let mut interface_modified = false;
             for (cell_name, cell_clone) in self.modules.get(&module_name).unwrap().cells.clone() {
-                if cell_clone.kind == "$_DFF_P_" {
+                if let Some(flip_flop) = FlipFlop::new(&cell_clone.kind) {
                     // Remove flip-flops by using the D port directly
                     let module = self.modules.get_mut(&module_name).unwrap();
-                    let data_wire = *cell_clone.connections.get("D").unwrap();
+                    let mut data_wire = *cell_clone.connections.get("D").unwrap();
                     let output_wire = *cell_clone.connections.get("Q").unwrap();
+                    let mut reset_value = module.init_value(output_wire);
+
+                    // Enables keep the value of the state input, and resets select the reset
+                    // value, in front of the D port. Asynchronous resets are applied at the clock
+                    // edge as well, since each cycle ends with one. Each mux takes its first wire
+                    // while the select input is active, where `None` is the data so far.
+                    let mut muxes = Vec::new();
+                    if let Some(polarity) = flip_flop.enable {
+                        let state_wire = module
+                            .ports
+                            .iter()
+                            .find(|(_, port)| {
+                                port.dir == PortDir::Output && port.wire == output_wire
+                            })
+                            .and_then(|(name, _)| module.ports.get(&format!("{name}.i")))
+                            .ok_or_else(|| {
+                                format!(
+                                    "Flip-flop `{cell_name}` in `{module_name}` has no state input"
+                                )
+                            })?
+                            .wire;
+                        muxes.push(("enable", "E", polarity, None, Some(state_wire)));
+                    }
+                    if let Some((polarity, value)) = flip_flop.reset {
+                        reset_value = Some(value);
+                        let reset = ("reset", "R", polarity, Some(Wire::Const(value)), None);
+                        if flip_flop.enable_first {
+                            muxes.insert(0, reset);
+                        } else {
+                            muxes.push(reset);
+                        }
+                    }
+
+                    for (suffix, select, polarity, active, inactive) in muxes {
+                        let active = active.unwrap_or(data_wire);
+                        let inactive = inactive.unwrap_or(data_wire);
+                        let (a, b) = if polarity {
+                            (inactive, active)
+                        } else {
+                            (active, inactive)
+                        };
+
+                        data_wire = Wire::Wire(next_wire_id);
+                        next_wire_id += 1;
+
+                        module.cells.insert(
+                            format!("{cell_name}.{suffix}"),
+                            Cell {
+                                kind: "$_MUX_".to_string(),
+                                port_dirs: [
+                                    ("A".to_string(), PortDir::Input),
+                                    ("B".to_string(), PortDir::Input),
+                                    ("S".to_string(), PortDir::Input),
+                                    ("Y".to_string(), PortDir::Output),
+                                ]
+                                .into_iter()
+                                .collect(),
+                                connections: [
+                                    ("A".to_string(), a),
+                                    ("B".to_string(), b),
+                                    (
+                                        "S".to_string(),
+                                        *cell_clone.connections.get(select).unwrap(),
+                                    ),
+                                    ("Y".to_string(), data_wire),
+                                ]
+                                .into_iter()
+                                .collect(),
+                            },
+                        );
+                    }
 
                     for cell in module.cells.values() {
                         for (connection_name, connection) in cell.connections.iter() {
@@ -111,13 +207,22 @@ impl Netlist {
                         }
                     }
 
-                    for port in module.ports.values_mut() {
+                    for (port_name, port) in module.ports.iter_mut() {
                         if port.wire == output_wire && port.dir == PortDir::Output {
                             port.wire = data_wire;
+
+                            if let Some(value) = reset_value {
+                                module.reset_values.insert(format!("{port_name}.i"), value);
+                            }
                         }
                     }
 
                     module.cells.remove(&cell_name).unwrap();
+                } else if FlipFlop::is_flip_flop(&cell_clone.kind) {
+                    return Err(format!(
+                        "Flip-flop `{cell_name}` in `{module_name}` has the unsupported type `{}`",
+                        cell_clone.kind
+                    ));
                 } else if let Some(submod_clone) = self.modules.get(&cell_clone.kind).cloned() {
                     let module = self.modules.get_mut(&module_name).unwrap();
                     let cell = module.cells.get_mut(&cell_name).unwrap();
@@ -265,6 +370,50 @@ impl Netlist {
                 }
             }
         }
+
+        self.propagate_reset_values();
+        Ok(())
+    }
+
+    /// Copies the reset values of submodule state inputs to the `{cell}..{port}.i` ports which
+    /// expose them
+    fn propagate_reset_values(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for module_name in self.modules.keys().cloned().collect::<Vec<_>>() {
+                let module = self.modules.get(&module_name).unwrap();
+                let mut new_values = Vec::new();
+                for (port_name, _) in module.input_ports() {
+                    if module.reset_values.contains_key(port_name) {
+                        continue;
+                    }
+
+                    let Some((cell_name, submod_port)) = port_name.split_once("..") else {
+                        continue;
+                    };
+                    let Some(submod) = module
+                        .cells
+                        .get(cell_name)
+                        .and_then(|cell| self.modules.get(&cell.kind))
+                    else {
+                        continue;
+                    };
+
+                    if let Some(&value) = submod.reset_values.get(submod_port) {
+                        new_values.push((port_name.clone(), value));
+                    }
+                }
+
+                changed = changed || !new_values.is_empty();
+                self.modules
+                    .get_mut(&module_name)
+                    .unwrap()
+                    .reset_values
+                    .extend(new_values);
+            }
+        }
     }
 
     pub fn show(&self) {
@@ -286,9 +435,50 @@ pub struct Module {
     pub attributes: HashMap<String, String>,
     pub ports: Map<String, Port>,
     pub cells: Map<String, Cell>,
+    #[serde(default)]
+    pub netnames: Map<String, NetName>,
+
+    /// Values of the state inputs after reset, from flip-flop resets and `init` attributes
+    #[serde(skip)]
+    pub reset_values: Map<String, bool>,
 }
 
 impl Module {
+    /// Value given to a wire by an `init` attribute
+    pub fn init_value(&self, wire: Wire) -> Option<bool> {
+        self.netnames.values().find_map(|netname| {
+            let init = netname.attributes.get("init")?;
+            let idx = netname
+                .bits
+                .iter()
+                .position(|bit| *bit == NetBit::from(wire))?;
+            match init.as_bytes().get(init.len().checked_sub(idx + 1)?)? {
+                b'0' => Some(false),
+                b'1' => Some(true),
+                _ => None,
+            }
+        })
+    }
+
+    /// Name of a wire from the net names, preferring names from the source
+    pub fn wire_name(&self, wire: Wire) -> Option<String> {
+        let bit = NetBit::from(wire);
+        let mut names = self
+            .netnames
+            .iter()
+            .filter_map(|(name, netname)| {
+                let idx = netname.bits.iter().position(|other| *other == bit)?;
+                Some(if netname.bits.len() == 1 {
+                    name.clone()
+                } else {
+                    format!("{name}[{idx}]")
+                })
+            })
+            .collect::<Vec<_>>();
+        names.sort_by_key(|name| name.starts_with('$'));
+        names.into_iter().next()
+    }
+
     pub fn input_ports(&self) -> impl Iterator<Item = (&String, &Port)> {
         self.ports
             .iter()
@@ -302,10 +492,72 @@ impl Module {
     }
 }
 
+/// Flip-flop with a positive clock, which takes the D port at each cycle unless it's reset or
+/// disabled
+struct FlipFlop {
+    /// Polarity and value of the reset
+    reset: Option<(bool, bool)>,
+    /// Polarity of the enable
+    enable: Option<bool>,
+    /// Whether the reset only applies while the flip-flop is enabled
+    enable_first: bool,
+}
+
+impl FlipFlop {
+    /// Parses the yosys cell types `$_DFF_P_`, `$_DFF_P??_`, `$_SDFF_P??_`, `$_DFFE_P?_`,
+    /// `$_DFFE_P???_`, `$_SDFFE_P???_` and `$_SDFFCE_P???_`
+    fn new(kind: &str) -> Option<Self> {
+        let (kind, flags) = kind.strip_suffix('_')?.rsplit_once('_')?;
+        let polarity = |flag: &u8| {
+            match flag {
+                b'P' => Some(true),
+                b'N' => Some(false),
+                _ => None,
+            }
+        };
+        let value = |flag: &u8| {
+            match flag {
+                b'0' => Some(false),
+                b'1' => Some(true),
+                _ => None,
+            }
+        };
+
+        let (reset, enable) = match (kind, flags.strip_prefix('P')?.as_bytes()) {
+            ("$_DFF", []) => (None, None),
+            ("$_DFF" | "$_SDFF", [reset, reset_value]) => {
+                (Some((polarity(reset)?, value(reset_value)?)), None)
+            }
+            ("$_DFFE", [enable]) => (None, Some(polarity(enable)?)),
+            ("$_DFFE" | "$_SDFFE" | "$_SDFFCE", [reset, reset_value, enable]) => {
+                (
+                    Some((polarity(reset)?, value(reset_value)?)),
+                    Some(polarity(enable)?),
+                )
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            reset,
+            enable,
+            enable_first: kind == "$_SDFFCE",
+        })
+    }
+
+    /// Whether a cell type is a flip-flop or latch, including the ones which aren't supported
+    fn is_flip_flop(kind: &str) -> bool {
+        ["$_DFF", "$_SDFF", "$_ALDFF", "$_DLATCH", "$_SR_"]
+            .iter()
+            .any(|prefix| kind.starts_with(prefix))
+    }
+}
+
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum Wire {
     Wire(usize),
     Const(bool),
+    Undef,
 }
 
 impl Serialize for Wire {
@@ -318,6 +570,7 @@ impl Serialize for Wire {
             Wire::Const(constant) => {
                 serializer.collect_seq(std::iter::once(if *constant { "1" } else { "0" }))
             }
+            Wire::Undef => serializer.collect_seq(std::iter::once("x")),
         }
     }
 }
@@ -345,6 +598,7 @@ impl<'de> Deserialize<'de> for Wire {
                 match s.as_str() {
                     "0" => Ok(Wire::Const(false)),
                     "1" => Ok(Wire::Const(true)),
+                    "x" => Ok(Wire::Undef),
                     other => {
                         Err(serde::de::Error::custom(format!(
                             "unexpected string for constant: {}",
@@ -357,6 +611,31 @@ impl<'de> Deserialize<'de> for Wire {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetName {
+    pub bits: Vec<NetBit>,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+}
+
+/// Bit of a multi-bit net, which is either a wire id or a constant such as `"0"` or `"x"`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NetBit {
+    Wire(usize),
+    Const(String),
+}
+
+impl From<Wire> for NetBit {
+    fn from(wire: Wire) -> Self {
+        match wire {
+            Wire::Wire(id) => NetBit::Wire(id),
+            Wire::Const(constant) => NetBit::Const(if constant { "1" } else { "0" }.to_string()),
+            Wire::Undef => NetBit::Const("x".to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Port {
     #[serde(rename = "direction")]
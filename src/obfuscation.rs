@@ -0,0 +1,168 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use serde::{Deserialize, Serialize};
+
+use crate::Map;
+
+/// Controls how the emitted macros are obfuscated
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObfuscationConfig {
+    /// Random macro and argument names, no docs, random macro order and no spaces between
+    /// arguments
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Seed for the random choices, which makes the output reproducible
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// File to write the `ObfuscationKey` to
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+    /// Chance of each equivalent rewrite of the gates in a module, from 0 for none to 1
+    #[serde(default)]
+    pub logic_intensity: f64,
+    /// Random tokens or swapped polarity for the values of the wires inside modules, with tables
+    /// re-encoded to match
+    #[serde(default)]
+    pub scramble_wires: bool,
+}
+
+impl ObfuscationConfig {
+    pub fn readable() -> Self {
+        Self {
+            enabled: false,
+            seed: None,
+            key: None,
+            logic_intensity: 0.0,
+            scramble_wires: false,
+        }
+    }
+
+    pub fn obfuscated() -> Self {
+        Self {
+            enabled: true,
+            seed: None,
+            key: None,
+            logic_intensity: 0.0,
+            scramble_wires: false,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn logic_intensity(mut self, intensity: f64) -> Self {
+        assert!((0.0..=1.0).contains(&intensity));
+        self.logic_intensity = intensity;
+        self
+    }
+
+    pub fn scramble_wires(mut self, scramble: bool) -> Self {
+        self.scramble_wires = scramble;
+        self
+    }
+
+    /// Source of the random choices, seeded from the OS unless a seed is set
+    pub fn rng(&self) -> SmallRng {
+        match self.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_os_rng(),
+        }
+    }
+
+    /// Separator between a table prefix from `get_alias` and the row key
+    pub fn prefix_sep(&self) -> &'static str {
+        if self.enabled { "" } else { "_" }
+    }
+}
+
+impl Default for ObfuscationConfig {
+    fn default() -> Self {
+        Self::obfuscated()
+    }
+}
+
+/// Readable name of an obfuscated name, and what it was generated for
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEntry {
+    pub name: String,
+    pub origin: String,
+}
+
+/// Maps the random names of an obfuscated header back to readable ones
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObfuscationKey {
+    /// Macros, defines and table prefixes
+    pub names: Map<String, KeyEntry>,
+    /// Parameters by the name of their macro, as they are only unique within a macro
+    pub params: Map<String, Map<String, KeyEntry>>,
+}
+
+impl ObfuscationKey {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        serde_json::from_str(&text).map_err(|err| format!("Invalid {}: {err}", path.display()))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, serde_json::to_string_pretty(self).unwrap())
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
+
+    /// Replaces the obfuscated names in a header, a preprocessor output or an error message.
+    /// Parameters are only replaced on the line which defines their macro.
+    pub fn deobfuscate(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+
+        for line in text.split_inclusive('\n') {
+            let params = line
+                .find("#define")
+                .map(|idx| line[idx + "#define".len()..].trim_start())
+                .and_then(|rest| {
+                    let (name, rest) = rest.split_at(identifier_len(rest));
+                    rest.starts_with('(')
+                        .then(|| self.params.get(name))
+                        .flatten()
+                });
+
+            let mut rest = line;
+            while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+                result.push_str(&rest[..start]);
+                rest = &rest[start..];
+
+                let (token, tail) = rest.split_at(identifier_len(rest));
+                let entry = params
+                    .and_then(|params| params.get(token))
+                    .or_else(|| self.names.get(token));
+                match entry {
+                    // Numbers such as `0x1` aren't identifiers
+                    Some(entry) if !token.starts_with(|c: char| c.is_ascii_digit()) => {
+                        result.push_str(&entry.name)
+                    }
+                    _ => result.push_str(token),
+                }
+                rest = tail;
+            }
+            result.push_str(rest);
+        }
+
+        result
+    }
+}
+
+fn identifier_len(text: &str) -> usize {
+    text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len())
+}
+
+fn default_enabled() -> bool {
+    true
+}
@@ -1,36 +1,90 @@
 use std::collections::HashMap;
+use std::fmt::Write;
 
-use crate::PREFIX_SEP;
-use crate::expr::Expr;
+use crate::Map;
+use crate::expr::{Expr, VarID};
+use crate::halt::HaltCondition;
 use crate::lut::Lut;
 use crate::r#macro::{Macro, MacroID};
+use crate::mapping::{MappingGoal, map_module};
 use crate::module::create_module;
-use crate::netlist::{Module, Netlist};
+use crate::netlist::{Cell, Module, Netlist, Wire};
+use crate::rewrite::rewrite_module;
+use crate::scope::MutScope;
 use crate::scope::global::GlobalScope;
+use crate::state::{StateLayout, port_identifier};
 
 pub struct Registry {
-    luts: HashMap<String, Lut>,
-    modules: HashMap<String, Module>,
+    luts: Map<String, Lut>,
+    modules: Map<String, Module>,
 
-    module_macros: HashMap<String, MacroID>,
+    module_macros: Map<String, MacroID>,
     paste_macros: HashMap<(usize, bool), MacroID>,
+    const_macros: HashMap<String, MacroID>,
     eval_macros: Vec<MacroID>,
+    exact_eval_macros: HashMap<usize, MacroID>,
     empty_macro: Option<MacroID>,
     obstruct_macro: Option<MacroID>,
+    expand_macro: Option<MacroID>,
     if_macro: Option<MacroID>,
+    unless_macro: Option<MacroID>,
+    apply_macro: Option<MacroID>,
+    nonempty_macro: Option<MacroID>,
+    split_head_macro: Option<MacroID>,
+    pick_macros: HashMap<usize, MacroID>,
+    probe_macro: Option<MacroID>,
+    check_macro: Option<MacroID>,
+    compl_macro: Option<MacroID>,
+    not_macro: Option<MacroID>,
+    bool_macro: Option<MacroID>,
+    rest_macro: Option<MacroID>,
+    seq_head_macro: Option<MacroID>,
+    while_macro: Option<MacroID>,
+    for_each_macro: Option<MacroID>,
+    repeat_n_macros: HashMap<usize, MacroID>,
+
+    max_lut_inputs: Option<usize>,
+    max_collapse_inputs: Option<usize>,
+    x_propagation: bool,
+    eval_branching: usize,
 }
 
 impl Registry {
+    /// Rescans needed by each cycle of a [`repeat_macro`](Registry::repeat_macro)
+    pub const REPEAT_RESCANS: usize = 1;
+
     pub fn new() -> Self {
         Self {
-            luts: HashMap::new(),
-            modules: HashMap::new(),
-            module_macros: HashMap::new(),
+            luts: Map::new(),
+            modules: Map::new(),
+            module_macros: Map::new(),
             paste_macros: HashMap::new(),
+            const_macros: HashMap::new(),
             eval_macros: Vec::new(),
+            exact_eval_macros: HashMap::new(),
             empty_macro: None,
             obstruct_macro: None,
+            expand_macro: None,
             if_macro: None,
+            unless_macro: None,
+            apply_macro: None,
+            nonempty_macro: None,
+            split_head_macro: None,
+            pick_macros: HashMap::new(),
+            probe_macro: None,
+            check_macro: None,
+            compl_macro: None,
+            not_macro: None,
+            bool_macro: None,
+            rest_macro: None,
+            seq_head_macro: None,
+            while_macro: None,
+            for_each_macro: None,
+            repeat_n_macros: HashMap::new(),
+            max_lut_inputs: None,
+            max_collapse_inputs: None,
+            x_propagation: false,
+            eval_branching: 4,
         }
     }
 
@@ -44,18 +98,74 @@ impl Registry {
     }
 
     pub fn register_lut(mut self, lut: Lut) -> Self {
-        assert!(self.name_available(lut.name));
-        self.luts.insert(lut.name.to_string(), lut);
+        assert!(self.name_available(&lut.name));
+        self.luts.insert(lut.name.clone(), lut);
+        self
+    }
+
+    /// Decompose luts with more inputs than `max_inputs` into a tree of smaller lookups
+    pub fn limit_lut_inputs(mut self, max_inputs: usize) -> Self {
+        assert_ne!(max_inputs, 0);
+        self.max_lut_inputs = Some(max_inputs);
+        self
+    }
+
+    /// Number of times each `EVAL{idx}` macro nests `EVAL{idx - 1}`. Smaller factors allow finer
+    /// rescan counts at the cost of more macros.
+    pub fn eval_branching(mut self, branching: usize) -> Self {
+        assert!(branching > 1);
+        self.eval_branching = branching;
+        self
+    }
+
+    /// Merge cones of lut cells in every module into synthesized luts with up to `max_inputs`
+    /// inputs, so that fewer lookups are needed
+    pub fn map_luts(mut self, max_inputs: usize, goal: MappingGoal) -> Self {
+        for module in self.modules.values_mut() {
+            let (mapped, new_luts) = map_module(module, &self.luts, max_inputs, goal);
+            *module = mapped;
+
+            for lut in new_luts {
+                self.luts.insert(lut.name.clone(), lut);
+            }
+        }
+
+        self
+    }
+
+    /// Turn modules with up to `max_inputs` inputs into a single lut instead of a netlist of
+    /// macros
+    pub fn collapse_modules(mut self, max_inputs: usize) -> Self {
+        assert_ne!(max_inputs, 0);
+        self.max_collapse_inputs = Some(max_inputs);
+        self
+    }
+
+    /// Extend every binary lut to a `0`/`1`/`X` alphabet, so that unknown values propagate through
+    /// the generated macros
+    pub fn propagate_x(mut self) -> Self {
+        self.x_propagation = true;
         self
     }
 
+    pub fn x_propagation(&self) -> bool {
+        self.x_propagation
+    }
+
+    pub fn max_lut_inputs(&self) -> Option<usize> {
+        self.max_lut_inputs
+    }
+
     pub fn module(global_scope: &mut GlobalScope, name: &str) -> Option<MacroID> {
         if let Some(&id) = global_scope.registry_mut().module_macros.get(name) {
             return Some(id);
         }
 
         if let Some(lut) = global_scope.registry().luts.get(name).cloned() {
-            let macro_id = lut.make_macro(global_scope);
+            let macro_id = global_scope
+                .registry()
+                .lut_variant(lut)
+                .make_macro(global_scope);
             global_scope
                 .registry_mut()
                 .module_macros
@@ -65,7 +175,40 @@ impl Registry {
         }
 
         if let Some(module) = global_scope.registry().modules.get(name).cloned() {
-            let macro_id = create_module(name, &module, global_scope);
+            let num_inputs = module.input_ports().count();
+            let macro_id = if global_scope
+                .registry()
+                .max_collapse_inputs
+                .is_some_and(|max_inputs| num_inputs != 0 && num_inputs <= max_inputs)
+            {
+                let lut = global_scope.registry().collapse_module(name, &module);
+                global_scope
+                    .registry()
+                    .lut_variant(lut)
+                    .make_macro(global_scope)
+            } else {
+                let intensity = global_scope.obfuscation().logic_intensity;
+                let module = if intensity > 0.0 {
+                    let binary_luts = global_scope
+                        .registry()
+                        .luts
+                        .iter()
+                        .filter(|(_, lut)| lut.is_binary())
+                        .map(|(name, _)| name.clone())
+                        .collect();
+                    let x_safe = global_scope.registry().x_propagation();
+                    rewrite_module(
+                        &module,
+                        &binary_luts,
+                        intensity,
+                        x_safe,
+                        &mut *global_scope.rng(),
+                    )
+                } else {
+                    module
+                };
+                create_module(name, &module, global_scope)
+            };
             global_scope
                 .registry_mut()
                 .module_macros
@@ -77,6 +220,37 @@ impl Registry {
         None
     }
 
+    /// Alphabet which the wires of a lut carry, or `None` if its values can't be scrambled
+    pub fn scrambled_alphabet(&self, name: &str) -> Option<Vec<String>> {
+        let lut = self.luts.get(name).filter(|lut| lut.is_binary())?;
+        Some(self.lut_variant(lut.clone()).alphabet)
+    }
+
+    /// Macro for a lut whose ports carry the given tokens instead of its alphabet. Unlike the
+    /// macros from `module`, these aren't shared.
+    pub fn encoded_lut(
+        global_scope: &mut GlobalScope,
+        name: &str,
+        encodings: &Map<String, Vec<String>>,
+    ) -> Option<MacroID> {
+        let lut = global_scope
+            .registry()
+            .lut_variant(global_scope.registry().luts.get(name)?.clone());
+        let encodings = lut
+            .input_names
+            .iter()
+            .chain(&lut.output_names)
+            .map(|port| {
+                encodings
+                    .get(port)
+                    .cloned()
+                    .unwrap_or_else(|| lut.alphabet.clone())
+            })
+            .collect();
+
+        Some(lut.encode(encodings).make_macro(global_scope))
+    }
+
     pub fn top_modules(global_scope: &mut GlobalScope) -> Vec<MacroID> {
         let mut macros = Vec::new();
 
@@ -153,6 +327,38 @@ impl Registry {
         macro_id
     }
 
+    pub fn const_macro(global_scope: &mut GlobalScope, value: &str) -> MacroID {
+        if let Some(&id) = global_scope.registry().const_macros.get(value) {
+            return id;
+        }
+
+        let mut scope = global_scope.new_scope();
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("CONST_{value}"), false),
+            expr: Expr::Text(value.to_string()),
+            inputs: vec![variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        global_scope
+            .registry_mut()
+            .const_macros
+            .insert(value.to_string(), id);
+        id
+    }
+
+    /// Number of extra rescans `EVAL{idx}` applies to its arguments, not counting the scan of the
+    /// call itself. This depends on the branching factor of the registry.
+    pub fn eval_rescans(&self, idx: usize) -> usize {
+        (0..=idx)
+            .map(|level| self.eval_branching.pow(level as u32))
+            .sum()
+    }
+
     pub fn eval_multiplier(global_scope: &mut GlobalScope, idx: usize) -> MacroID {
         if let Some(id) = global_scope.registry().eval_macros.get(idx) {
             return *id;
@@ -171,28 +377,19 @@ impl Registry {
                 doc_name: None,
             })
         } else {
-            let prev = Box::new(Expr::Macro(Registry::eval_multiplier(
-                global_scope,
-                idx - 1,
-            )));
+            let prev = Registry::eval_multiplier(global_scope, idx - 1);
+            let branching = global_scope.registry().eval_branching;
             let mut scope = global_scope.new_scope();
             let variadic = scope.new_var("variadic", false, true, None);
             scope.new_macro(Macro {
                 scope_id: scope.id,
                 name: scope.get_alias(&format!("EVAL{idx}"), false),
-                expr: Expr::Call {
-                    r#macro: prev.clone(),
-                    args: vec![Expr::Call {
-                        r#macro: prev.clone(),
-                        args: vec![Expr::Call {
-                            r#macro: prev.clone(),
-                            args: vec![Expr::Call {
-                                r#macro: prev,
-                                args: vec![Expr::Var(variadic)],
-                            }],
-                        }],
-                    }],
-                },
+                expr: (0..branching).fold(Expr::Var(variadic), |expr, _| {
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Macro(prev)),
+                        args: vec![expr],
+                    }
+                }),
                 inputs: vec![variadic],
                 variadicified_vars: None,
                 calling_split: None,
@@ -204,6 +401,51 @@ impl Registry {
         id
     }
 
+    /// Creates a macro which applies exactly `rescans` extra rescans to its arguments. It nests
+    /// the `EVAL{idx}` macros according to the digits of `rescans - 1` in their mixed radix.
+    pub fn eval_macro(global_scope: &mut GlobalScope, rescans: usize) -> MacroID {
+        assert_ne!(rescans, 0);
+        if let Some(id) = global_scope.registry().exact_eval_macros.get(&rescans) {
+            return *id;
+        }
+
+        // The macro itself already adds one rescan
+        let mut levels = Vec::new();
+        let mut remaining = rescans - 1;
+        while remaining != 0 {
+            let registry = global_scope.registry();
+            let idx = (0..)
+                .take_while(|&idx| registry.eval_rescans(idx) <= remaining)
+                .last()
+                .unwrap();
+            remaining -= registry.eval_rescans(idx);
+            levels.push(Registry::eval_multiplier(global_scope, idx));
+        }
+
+        let mut scope = global_scope.new_scope();
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("EVAL_{rescans}"), false),
+            expr: levels
+                .into_iter()
+                .rev()
+                .fold(Expr::Var(variadic), |expr, level| {
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Macro(level)),
+                        args: vec![expr],
+                    }
+                }),
+            inputs: vec![variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().exact_eval_macros.insert(rescans, id);
+        id
+    }
+
     pub fn empty_macro(global_scope: &mut GlobalScope) -> MacroID {
         if let Some(empty) = global_scope.registry().empty_macro {
             return empty;
@@ -282,148 +524,1863 @@ impl Registry {
         obstruct
     }
 
-    pub fn if_macro(global_scope: &mut GlobalScope) -> MacroID {
-        if let Some(id) = global_scope.registry().if_macro {
+    /// `PROBE(x)` expands to `x, 1,`, which [`check_macro`](Registry::check_macro) detects.
+    pub fn probe_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().probe_macro {
             return id;
         }
 
         let mut scope = global_scope.new_scope();
-        let variadic = scope.new_var("variadic", false, true, None);
-        let expand = scope.new_macro(Macro {
+        let var = scope.new_var("x", false, false, None);
+        let id = scope.new_macro(Macro {
             scope_id: scope.id,
-            name: scope.get_alias("EXPAND", false),
-            expr: Expr::Var(variadic),
-            inputs: vec![variadic],
+            name: scope.get_alias("PROBE", false),
+            expr: Expr::List(
+                vec![
+                    Expr::Var(var),
+                    Expr::Text("1".to_string()),
+                    Expr::List(Vec::new(), ""),
+                ],
+                ", ",
+            ),
+            inputs: vec![var],
             variadicified_vars: None,
             calling_split: None,
             doc_name: None,
         });
-        let eat = Registry::empty_macro(scope.global);
 
-        let prefix = scope.get_alias("IF", true);
-        scope.define(
-            format!("{prefix}{PREFIX_SEP}0"),
-            scope.get_macro(eat).name.clone(),
-        );
-        scope.define(
-            format!("{prefix}{PREFIX_SEP}1"),
-            scope.get_macro(expand).name.clone(),
-        );
+        scope.registry_mut().probe_macro = Some(id);
+        id
+    }
+
+    /// `CHECK(...)` expands to `1` if its arguments expanded to a
+    /// [`probe_macro`](Registry::probe_macro), and to `0` otherwise.
+    pub fn check_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().check_macro {
+            return id;
+        }
 
-        let paste = Registry::paste_macro(scope.global, 2, true);
+        let mut scope = global_scope.new_scope();
 
-        let var = scope.new_var("cont", false, false, None);
+        let var = scope.new_var("x", false, false, None);
+        let value = scope.new_var("n", false, false, None);
+        let variadic = scope.new_var("variadic", false, true, None);
+        let check_n = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("CHECK_N", false),
+            expr: Expr::Var(value),
+            inputs: vec![var, value, variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let variadic = scope.new_var("variadic", false, true, None);
         let id = scope.new_macro(Macro {
             scope_id: scope.id,
-            name: scope.get_alias("IF", false),
+            name: scope.get_alias("CHECK", false),
             expr: Expr::Call {
-                r#macro: Box::new(Expr::Macro(paste)),
-                args: vec![Expr::Text(format!("{prefix}{PREFIX_SEP}")), Expr::Var(var)],
+                r#macro: Box::new(Expr::Macro(check_n)),
+                args: vec![
+                    Expr::Var(variadic),
+                    Expr::Text("0".to_string()),
+                    Expr::List(Vec::new(), ""),
+                ],
             },
-            inputs: vec![var],
+            inputs: vec![variadic],
             variadicified_vars: None,
             calling_split: None,
             doc_name: None,
         });
 
-        scope.registry_mut().if_macro = Some(id);
+        scope.registry_mut().check_macro = Some(id);
         id
     }
 
-    pub fn repeat_macro(global_scope: &mut GlobalScope, macro_id: MacroID, cont_var: &str) {
-        let r#macro = global_scope.get_macro(macro_id).clone();
-        let scope_id = r#macro.scope_id;
-        let macro_name = format!("REPEAT_{}", &r#macro.name);
+    /// `NONEMPTY(seq)` expands to `1` if `seq` starts with a parenthesized element, and to `0` if
+    /// it is empty.
+    pub fn nonempty_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().nonempty_macro {
+            return id;
+        }
 
-        let obstruct_macro = Registry::obstruct_macro(global_scope);
-        let if_macro = Registry::if_macro(global_scope);
+        let probe = Registry::probe_macro(global_scope);
+        let check = Registry::check_macro(global_scope);
+        let mut scope = global_scope.new_scope();
 
-        let mut scope = global_scope.get_mut_scope(scope_id);
+        let variadic = scope.new_var("variadic", false, true, None);
+        let paren_probe = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("PAREN_PROBE", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(probe)),
+                args: vec![Expr::Text("~".to_string())],
+            },
+            inputs: vec![variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
 
-        let mut repeat_inputs = Vec::new();
-        let mut cont_var_id = None;
-        for output in scope.local().output_names.as_ref().unwrap().clone() {
-            if let Some(var) = scope.scope().local().input_map.get(&format!("{output}.i")) {
-                repeat_inputs.push(*var);
-            } else {
-                let var = scope.new_var(&output, false, false, None);
-                repeat_inputs.push(var);
+        let seq = scope.new_var("seq", false, false, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("NONEMPTY", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(check)),
+                args: vec![Expr::List(
+                    vec![Expr::Macro(paren_probe), Expr::Var(seq)],
+                    " ",
+                )],
+            },
+            inputs: vec![seq],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
 
-                if &output == cont_var {
-                    cont_var_id = Some(var);
-                }
-            }
-        }
+        scope.registry_mut().nonempty_macro = Some(id);
+        id
+    }
 
-        let mut call_inputs = Vec::new();
-        let mut passthrough = Vec::new();
-        for &input in &r#macro.inputs {
-            call_inputs.push(Expr::Var(input));
-            if !repeat_inputs.contains(&input) {
-                repeat_inputs.push(input);
-                passthrough.push(Expr::Var(input));
-            }
+    /// `SPLIT_HEAD seq` splits the first element off a sequence such as `(a, b)(c, d)`, giving
+    /// `a, b, (c, d)`.
+    pub fn split_head_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().split_head_macro {
+            return id;
         }
 
-        let indirect_macro = scope.new_macro(Macro {
+        let mut scope = global_scope.new_scope();
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
             scope_id: scope.id,
-            name: scope.get_alias(&format!("{macro_name}_INDIRECT"), false),
-            expr: Expr::List(Vec::new(), ""),
-            inputs: Vec::new(),
+            name: scope.get_alias("SPLIT_HEAD", false),
+            expr: Expr::List(vec![Expr::Var(variadic), Expr::List(Vec::new(), "")], ", "),
+            inputs: vec![variadic],
             variadicified_vars: None,
             calling_split: None,
             doc_name: None,
         });
 
-        let repeat_macro = scope.new_macro(Macro {
+        scope.registry_mut().split_head_macro = Some(id);
+        id
+    }
+
+    pub fn apply_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().apply_macro {
+            return id;
+        }
+
+        let mut scope = global_scope.new_scope();
+        let var = scope.new_var("m", false, false, None);
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
             scope_id: scope.id,
-            name: scope.get_alias(&macro_name, false),
+            name: scope.get_alias("APPLY", false),
             expr: Expr::Call {
-                r#macro: Box::new(Expr::Call {
-                    r#macro: Box::new(Expr::Macro(if_macro)),
-                    args: vec![Expr::Var(cont_var_id.unwrap())],
-                }),
-                args: vec![Expr::Call {
-                    r#macro: Box::new(Expr::Call {
-                        r#macro: Box::new(Expr::Call {
-                            r#macro: Box::new(Expr::Macro(obstruct_macro)),
-                            args: vec![Expr::Macro(indirect_macro)],
-                        }),
-                        args: vec![],
-                    }),
-                    args: std::iter::once(Expr::Call {
-                        r#macro: Box::new(Expr::Macro(macro_id)),
-                        args: call_inputs,
-                    })
-                    .chain(passthrough.into_iter())
-                    .collect(),
-                }],
+                r#macro: Box::new(Expr::Var(var)),
+                args: vec![Expr::Var(variadic)],
             },
-            inputs: repeat_inputs,
+            inputs: vec![var, variadic],
             variadicified_vars: None,
             calling_split: None,
             doc_name: None,
         });
 
-        scope.get_mut_macro(indirect_macro).expr = Expr::Macro(repeat_macro);
+        scope.registry_mut().apply_macro = Some(id);
+        id
     }
 
-    fn name_available(&self, name: &str) -> bool {
-        !self
-            .luts
-            .keys()
-            .chain(self.modules.keys())
-            .any(|existing| existing == name)
+    /// `PICK{idx}(...)` expands to argument `idx` of a list, such as the outputs of a macro.
+    pub fn pick_macro(global_scope: &mut GlobalScope, idx: usize) -> MacroID {
+        if let Some(&id) = global_scope.registry().pick_macros.get(&idx) {
+            return id;
+        }
+
+        let mut scope = global_scope.new_scope();
+        let mut inputs = (0..=idx)
+            .map(|arg| scope.new_var(&format!("a{arg}"), false, false, None))
+            .collect::<Vec<_>>();
+        let picked = inputs[idx];
+        inputs.push(scope.new_var("variadic", false, true, None));
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("PICK{idx}"), false),
+            expr: Expr::Var(picked),
+            inputs,
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().pick_macros.insert(idx, id);
+        id
     }
-}
 
-impl Default for Registry {
-    fn default() -> Self {
-        Self::new()
-            .register_lut(Lut::not())
-            .register_lut(Lut::or())
-            .register_lut(Lut::and())
-            .register_lut(Lut::xor())
-            .register_lut(Lut::mux())
+    pub fn expand_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().expand_macro {
+            return id;
+        }
+
+        let mut scope = global_scope.new_scope();
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("EXPAND", false),
+            expr: Expr::Var(variadic),
+            inputs: vec![variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().expand_macro = Some(id);
+        id
+    }
+
+    /// `IF(cont)(...)` expands to its arguments if `cont` is `1`, and to nothing if it is `0`.
+    pub fn if_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().if_macro {
+            return id;
+        }
+
+        let id = Registry::condition_macro(global_scope, "IF", true);
+        global_scope.registry_mut().if_macro = Some(id);
+        id
+    }
+
+    /// `UNLESS(cont)(...)` expands to its arguments if `cont` is `0`, and to nothing if it is `1`.
+    pub fn unless_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().unless_macro {
+            return id;
+        }
+
+        let id = Registry::condition_macro(global_scope, "UNLESS", false);
+        global_scope.registry_mut().unless_macro = Some(id);
+        id
     }
+
+    fn condition_macro(global_scope: &mut GlobalScope, name: &str, expand_on: bool) -> MacroID {
+        let expand = Registry::expand_macro(global_scope);
+        let eat = Registry::empty_macro(global_scope);
+        let paste = Registry::paste_macro(global_scope, 2, true);
+
+        let mut scope = global_scope.new_scope();
+        let prefix = scope.get_alias(name, true);
+        let sep = scope.prefix_sep();
+        for value in [false, true] {
+            let target = if value == expand_on { expand } else { eat };
+            scope.define(
+                format!("{prefix}{sep}{}", value as u8),
+                scope.get_macro(target).name.clone(),
+            );
+        }
+
+        let var = scope.new_var("cont", false, false, None);
+        scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(name, false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(paste)),
+                args: vec![Expr::Text(format!("{prefix}{sep}")), Expr::Var(var)],
+            },
+            inputs: vec![var],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        })
+    }
+
+    /// `COMPL(b)` expands to `1` if `b` is `0`, and to `0` if it is `1`.
+    pub fn compl_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().compl_macro {
+            return id;
+        }
+
+        let paste = Registry::paste_macro(global_scope, 2, true);
+        let mut scope = global_scope.new_scope();
+        let prefix = scope.get_alias("COMPL", true);
+        let sep = scope.prefix_sep();
+        for value in [false, true] {
+            scope.define(
+                format!("{prefix}{sep}{}", value as u8),
+                (!value as u8).to_string(),
+            );
+        }
+
+        let var = scope.new_var("b", false, false, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("COMPL", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(paste)),
+                args: vec![Expr::Text(format!("{prefix}{sep}")), Expr::Var(var)],
+            },
+            inputs: vec![var],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().compl_macro = Some(id);
+        id
+    }
+
+    /// `NOT(x)` expands to `1` if `x` is `0`, and to `0` for any other identifier or number.
+    pub fn not_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().not_macro {
+            return id;
+        }
+
+        let probe = Registry::probe_macro(global_scope);
+        let check = Registry::check_macro(global_scope);
+        let paste = Registry::paste_macro(global_scope, 2, true);
+        let mut scope = global_scope.new_scope();
+        let prefix = scope.get_alias("NOT", true);
+        let sep = scope.prefix_sep();
+        let probe_name = scope.get_macro(probe).name.clone();
+        scope.define(format!("{prefix}{sep}0"), format!("{probe_name}(~)"));
+
+        let var = scope.new_var("x", false, false, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("NOT", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(check)),
+                args: vec![Expr::Call {
+                    r#macro: Box::new(Expr::Macro(paste)),
+                    args: vec![Expr::Text(format!("{prefix}{sep}")), Expr::Var(var)],
+                }],
+            },
+            inputs: vec![var],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().not_macro = Some(id);
+        id
+    }
+
+    /// `BOOL(x)` expands to `0` if `x` is `0`, and to `1` for any other identifier or number.
+    pub fn bool_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().bool_macro {
+            return id;
+        }
+
+        let compl = Registry::compl_macro(global_scope);
+        let not = Registry::not_macro(global_scope);
+        let mut scope = global_scope.new_scope();
+        let var = scope.new_var("x", false, false, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("BOOL", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(compl)),
+                args: vec![Expr::Call {
+                    r#macro: Box::new(Expr::Macro(not)),
+                    args: vec![Expr::Var(var)],
+                }],
+            },
+            inputs: vec![var],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().bool_macro = Some(id);
+        id
+    }
+
+    /// `REST(x, ...)` drops the first element of a list.
+    pub fn rest_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().rest_macro {
+            return id;
+        }
+
+        let mut scope = global_scope.new_scope();
+        let var = scope.new_var("x", false, false, None);
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("REST", false),
+            expr: Expr::Var(variadic),
+            inputs: vec![var, variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().rest_macro = Some(id);
+        id
+    }
+
+    /// `SEQ_HEAD seq` splits the first element off a sequence such as `(a, b)(c, d)` like
+    /// [`split_head_macro`](Registry::split_head_macro), but keeps it as a tuple, giving
+    /// `(a, b), (c, d)`.
+    pub fn seq_head_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().seq_head_macro {
+            return id;
+        }
+
+        let mut scope = global_scope.new_scope();
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("SEQ_HEAD", false),
+            expr: Expr::List(
+                vec![
+                    Expr::List(
+                        vec![
+                            Expr::Text("(".to_string()),
+                            Expr::Var(variadic),
+                            Expr::Text(")".to_string()),
+                        ],
+                        "",
+                    ),
+                    Expr::List(Vec::new(), ""),
+                ],
+                ", ",
+            ),
+            inputs: vec![variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().seq_head_macro = Some(id);
+        id
+    }
+
+    /// `WHILE(pred, op, ...)` applies `op` to the state in its variadic arguments for as long as
+    /// `pred` of the state isn't `0`, and expands to the final state. Each iteration needs one
+    /// rescan, so calls have to be wrapped in an `EVAL` macro.
+    pub fn while_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().while_macro {
+            return id;
+        }
+
+        let bool_macro = Registry::bool_macro(global_scope);
+        let obstruct_macro = Registry::obstruct_macro(global_scope);
+        let if_macro = Registry::if_macro(global_scope);
+        let unless_macro = Registry::unless_macro(global_scope);
+
+        let mut scope = global_scope.new_scope();
+        let pred = scope.new_var("pred", false, false, None);
+        let op = scope.new_var("op", false, false, None);
+        let state = scope.new_var("variadic", false, true, None);
+
+        let indirect_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("WHILE_INDIRECT", false),
+            expr: Expr::List(Vec::new(), ""),
+            inputs: Vec::new(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let cont = Expr::Call {
+            r#macro: Box::new(Expr::Macro(bool_macro)),
+            args: vec![Expr::Call {
+                r#macro: Box::new(Expr::Var(pred)),
+                args: vec![Expr::Var(state)],
+            }],
+        };
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("WHILE", false),
+            expr: Expr::List(
+                vec![
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(if_macro)),
+                            args: vec![cont.clone()],
+                        }),
+                        args: vec![Expr::Call {
+                            r#macro: Box::new(Expr::Call {
+                                r#macro: Box::new(Expr::Call {
+                                    r#macro: Box::new(Expr::Macro(obstruct_macro)),
+                                    args: vec![Expr::Macro(indirect_macro)],
+                                }),
+                                args: vec![],
+                            }),
+                            args: vec![
+                                Expr::Var(pred),
+                                Expr::Var(op),
+                                Expr::Call {
+                                    r#macro: Box::new(Expr::Var(op)),
+                                    args: vec![Expr::Var(state)],
+                                },
+                            ],
+                        }],
+                    },
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(unless_macro)),
+                            args: vec![cont],
+                        }),
+                        args: vec![Expr::Var(state)],
+                    },
+                ],
+                " ",
+            ),
+            inputs: vec![pred, op, state],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.get_mut_macro(indirect_macro).expr = Expr::Macro(id);
+        scope.registry_mut().while_macro = Some(id);
+        id
+    }
+
+    /// `FOR_EACH(m, seq)` expands to `m` applied to every element of a sequence such as
+    /// `(a, b)(c, d)`, giving `m(a, b) m(c, d)`. Each element needs one rescan, so calls have to
+    /// be wrapped in an `EVAL` macro.
+    pub fn for_each_macro(global_scope: &mut GlobalScope) -> MacroID {
+        if let Some(id) = global_scope.registry().for_each_macro {
+            return id;
+        }
+
+        let nonempty_macro = Registry::nonempty_macro(global_scope);
+        let seq_head_macro = Registry::seq_head_macro(global_scope);
+        let obstruct_macro = Registry::obstruct_macro(global_scope);
+        let if_macro = Registry::if_macro(global_scope);
+
+        let mut scope = global_scope.new_scope();
+        let indirect_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("FOR_EACH_INDIRECT", false),
+            expr: Expr::List(Vec::new(), ""),
+            inputs: Vec::new(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let m = scope.new_var("m", false, false, None);
+        let seq = scope.new_var("seq", false, false, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("FOR_EACH", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Call {
+                    r#macro: Box::new(Expr::Macro(if_macro)),
+                    args: vec![Expr::Call {
+                        r#macro: Box::new(Expr::Macro(nonempty_macro)),
+                        args: vec![Expr::Var(seq)],
+                    }],
+                }),
+                args: vec![Expr::Call {
+                    r#macro: Box::new(Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(obstruct_macro)),
+                            args: vec![Expr::Macro(indirect_macro)],
+                        }),
+                        args: vec![],
+                    }),
+                    args: vec![
+                        Expr::Var(m),
+                        Expr::List(vec![Expr::Macro(seq_head_macro), Expr::Var(seq)], " "),
+                    ],
+                }],
+            },
+            inputs: vec![m, seq],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        // Applies `m` to the split off element and continues with the rest
+        let m = scope.new_var("m", false, false, None);
+        let head = scope.new_var("head", false, false, None);
+        let rest = scope.new_var("rest", false, false, None);
+        let step_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("FOR_EACH_STEP", false),
+            expr: Expr::List(
+                vec![
+                    Expr::List(vec![Expr::Var(m), Expr::Var(head)], " "),
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Macro(id)),
+                        args: vec![Expr::Var(m), Expr::Var(rest)],
+                    },
+                ],
+                " ",
+            ),
+            inputs: vec![m, head, rest],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.get_mut_macro(indirect_macro).expr = Expr::Macro(step_macro);
+        scope.registry_mut().for_each_macro = Some(id);
+        id
+    }
+
+    /// `REPEAT(n, m, ...)` expands to `m(0, ...) m(1, ...) ... m(n - 1, ...)` for a decimal `n` of
+    /// up to `max_count`. The calls are unrolled, so no `EVAL` is needed.
+    pub fn repeat_n_macro(global_scope: &mut GlobalScope, max_count: usize) -> MacroID {
+        if let Some(&id) = global_scope.registry().repeat_n_macros.get(&max_count) {
+            return id;
+        }
+
+        let paste = Registry::paste_macro(global_scope, 2, true);
+        let mut scope = global_scope.new_scope();
+        let prefix = scope.get_alias("REPEAT", true);
+        let sep = scope.prefix_sep();
+
+        let mut previous = None;
+        for count in 0..=max_count {
+            let m = scope.new_var("m", false, false, None);
+            let variadic = scope.new_var("variadic", false, true, None);
+            let expr = match previous {
+                Some(previous) => {
+                    Expr::List(
+                        vec![
+                            Expr::Call {
+                                r#macro: Box::new(Expr::Macro(previous)),
+                                args: vec![Expr::Var(m), Expr::Var(variadic)],
+                            },
+                            Expr::Call {
+                                r#macro: Box::new(Expr::Var(m)),
+                                args: vec![
+                                    Expr::Text((count - 1).to_string()),
+                                    Expr::Var(variadic),
+                                ],
+                            },
+                        ],
+                        " ",
+                    )
+                }
+                None => Expr::List(Vec::new(), ""),
+            };
+
+            previous = Some(scope.new_macro(Macro {
+                scope_id: scope.id,
+                name: format!("{prefix}{sep}{count}"),
+                expr,
+                inputs: vec![m, variadic],
+                variadicified_vars: None,
+                calling_split: None,
+                doc_name: None,
+            }));
+        }
+
+        let count = scope.new_var("n", false, false, None);
+        let m = scope.new_var("m", false, false, None);
+        let variadic = scope.new_var("variadic", false, true, None);
+        let id = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias("REPEAT", false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Call {
+                    r#macro: Box::new(Expr::Macro(paste)),
+                    args: vec![Expr::Text(format!("{prefix}{sep}")), Expr::Var(count)],
+                }),
+                args: vec![Expr::Var(m), Expr::Var(variadic)],
+            },
+            inputs: vec![count, m, variadic],
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.registry_mut().repeat_n_macros.insert(max_count, id);
+        id
+    }
+
+    /// Creates a macro which keeps stepping a sequential macro until its outputs meet the `halt`
+    /// condition. It takes the outputs of the previous cycle followed by the non-state inputs, and
+    /// returns the outputs of the cycle which met the condition. Each cycle needs
+    /// [`REPEAT_RESCANS`](Registry::REPEAT_RESCANS) rescans, so calls have to be wrapped in an
+    /// `EVAL` macro.
+    pub fn repeat_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        halt: &HaltCondition,
+    ) -> Result<MacroID, String> {
+        Registry::loop_macro(global_scope, macro_id, halt, None)
+    }
+
+    /// Like [`repeat_macro`](Registry::repeat_macro), but records the `traced` outputs (or all
+    /// outputs if `None`) of every cycle. It takes an extra trailing argument with the trace so
+    /// far, which should be empty initially, and returns the trace as a list of parenthesized
+    /// tuples such as `(0, 1)(1, 1)(1, 0)`.
+    pub fn trace_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        halt: &HaltCondition,
+        traced: Option<&[&str]>,
+    ) -> Result<MacroID, String> {
+        let r#macro = global_scope.get_macro(macro_id);
+        let output_names = global_scope
+            .get_scope(r#macro.scope_id)
+            .local()
+            .output_names
+            .clone()
+            .unwrap();
+
+        let traced = match traced {
+            Some(traced) => {
+                traced
+                    .iter()
+                    .map(|name| output_position(&output_names, name, &r#macro.name))
+                    .collect::<Result<_, _>>()?
+            }
+            None => (0..output_names.len()).collect(),
+        };
+
+        Registry::loop_macro(global_scope, macro_id, halt, Some(traced))
+    }
+
+    fn loop_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        halt: &HaltCondition,
+        traced: Option<Vec<usize>>,
+    ) -> Result<MacroID, String> {
+        let r#macro = global_scope.get_macro(macro_id).clone();
+        let scope_id = r#macro.scope_id;
+        let macro_name = if traced.is_some() {
+            format!("TRACE_{}", &r#macro.name)
+        } else {
+            format!("REPEAT_{}", &r#macro.name)
+        };
+
+        let output_names = global_scope
+            .get_scope(scope_id)
+            .local()
+            .output_names
+            .clone()
+            .unwrap();
+        let halt_outputs =
+            halt_positions(global_scope.registry(), halt, &output_names, &r#macro.name)?;
+
+        // Outputs which are already a continue flag don't need a lut
+        let halt_macro = match halt {
+            HaltCondition::Low(_) => None,
+            _ => {
+                let lut = halt.lut(&format!("{macro_name}_HALT"));
+                let halt_macro = lut.make_macro(global_scope);
+                let mut positions = lut
+                    .input_names
+                    .iter()
+                    .zip(&halt_outputs)
+                    .map(|(name, &output)| {
+                        (
+                            global_scope
+                                .get_macro(halt_macro)
+                                .input_position(name, global_scope)
+                                .unwrap(),
+                            output,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                positions.sort_by_key(|(idx, _)| *idx);
+                Some((halt_macro, positions))
+            }
+        };
+
+        let obstruct_macro = Registry::obstruct_macro(global_scope);
+        let if_macro = Registry::if_macro(global_scope);
+        let unless_macro = Registry::unless_macro(global_scope);
+
+        let layout = StateLayout::new(global_scope, macro_id);
+        let mut scope = global_scope.get_mut_scope(scope_id);
+        let (mut loop_inputs, output_vars, mut passthrough) =
+            Registry::feedback_vars(&mut scope, &r#macro, &layout);
+
+        let cont = match halt_macro {
+            Some((halt_macro, positions)) => {
+                Expr::Call {
+                    r#macro: Box::new(Expr::Macro(halt_macro)),
+                    args: positions
+                        .into_iter()
+                        .map(|(_, output)| Expr::Var(output_vars[output]))
+                        .collect(),
+                }
+            }
+            None => Expr::Var(output_vars[halt_outputs[0]]),
+        };
+
+        let call_inputs = r#macro
+            .inputs
+            .iter()
+            .map(|&input| Expr::Var(input))
+            .collect::<Vec<_>>();
+
+        // Returned values and the trace are extended by this cycle's outputs
+        let mut results = output_vars
+            .iter()
+            .map(|&var| Expr::Var(var))
+            .collect::<Vec<_>>();
+        if let Some(traced) = &traced {
+            let trace_var = scope.new_var("trace", false, false, None);
+            loop_inputs.push(trace_var);
+
+            let trace = Expr::List(
+                vec![
+                    Expr::Var(trace_var),
+                    Expr::Text("(".to_string()),
+                    Expr::List(
+                        traced
+                            .iter()
+                            .map(|&idx| Expr::Var(output_vars[idx]))
+                            .collect(),
+                        ", ",
+                    ),
+                    Expr::Text(")".to_string()),
+                ],
+                "",
+            );
+            passthrough.push(trace.clone());
+            results = vec![trace];
+        }
+
+        let indirect_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("{macro_name}_INDIRECT"), false),
+            expr: Expr::List(Vec::new(), ""),
+            inputs: Vec::new(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let loop_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&macro_name, false),
+            expr: Expr::List(
+                vec![
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(if_macro)),
+                            args: vec![cont.clone()],
+                        }),
+                        args: vec![Expr::Call {
+                            r#macro: Box::new(Expr::Call {
+                                r#macro: Box::new(Expr::Call {
+                                    r#macro: Box::new(Expr::Macro(obstruct_macro)),
+                                    args: vec![Expr::Macro(indirect_macro)],
+                                }),
+                                args: vec![],
+                            }),
+                            args: std::iter::once(Expr::Call {
+                                r#macro: Box::new(Expr::Macro(macro_id)),
+                                args: call_inputs,
+                            })
+                            .chain(passthrough)
+                            .collect(),
+                        }],
+                    },
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(unless_macro)),
+                            args: vec![cont],
+                        }),
+                        args: results,
+                    },
+                ],
+                " ",
+            ),
+            inputs: loop_inputs,
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.get_mut_macro(indirect_macro).expr = Expr::Macro(loop_macro);
+        Ok(loop_macro)
+    }
+
+    /// Creates a `RUN_<name>` macro which runs a sequential macro from the given inputs until its
+    /// outputs meet the `halt` condition. An `EVAL` macro with exactly the rescans needed for
+    /// `max_cycles` cycles is applied internally. A run which doesn't halt within `max_cycles`
+    /// cycles leaves an unexpanded call to the repeat macro instead of the outputs.
+    pub fn run_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        halt: &HaltCondition,
+        max_cycles: usize,
+    ) -> Result<MacroID, String> {
+        let repeat_macro = Registry::repeat_macro(global_scope, macro_id, halt)?;
+        Ok(Registry::entry_macro(
+            global_scope,
+            macro_id,
+            repeat_macro,
+            "RUN",
+            max_cycles,
+        ))
+    }
+
+    /// Creates a `RUN_TRACE_<name>` macro which runs a sequential macro like
+    /// [`run_macro`](Registry::run_macro), but returns the trace of a
+    /// [`trace_macro`](Registry::trace_macro) instead of the final outputs.
+    pub fn run_trace_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        halt: &HaltCondition,
+        traced: Option<&[&str]>,
+        max_cycles: usize,
+    ) -> Result<MacroID, String> {
+        let trace_macro = Registry::trace_macro(global_scope, macro_id, halt, traced)?;
+        Ok(Registry::entry_macro(
+            global_scope,
+            macro_id,
+            trace_macro,
+            "RUN_TRACE",
+            max_cycles,
+        ))
+    }
+
+    fn entry_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        loop_macro: MacroID,
+        prefix: &str,
+        max_cycles: usize,
+    ) -> MacroID {
+        let rescans = (max_cycles - 1) * Registry::REPEAT_RESCANS;
+        let eval_macro = (rescans != 0).then(|| Registry::eval_macro(global_scope, rescans));
+        let apply_macro = Registry::apply_macro(global_scope);
+
+        let r#macro = global_scope.get_macro(macro_id).clone();
+        let loop_inputs = global_scope.get_macro(loop_macro).inputs.clone();
+        let mut scope = global_scope.get_mut_scope(r#macro.scope_id);
+        let num_outputs = scope.local().output_names.as_ref().unwrap().len();
+
+        // The first cycle is run directly, the passthrough inputs and an empty trace follow its
+        // outputs
+        let args = std::iter::once(Expr::Macro(loop_macro))
+            .chain(std::iter::once(Expr::Call {
+                r#macro: Box::new(Expr::Macro(macro_id)),
+                args: r#macro
+                    .inputs
+                    .iter()
+                    .map(|&input| Expr::Var(input))
+                    .collect(),
+            }))
+            .chain(loop_inputs[num_outputs..].iter().map(|input| {
+                if r#macro.inputs.contains(input) {
+                    Expr::Var(*input)
+                } else {
+                    Expr::Text(String::new())
+                }
+            }))
+            .collect();
+
+        let mut expr = Expr::Call {
+            r#macro: Box::new(Expr::Macro(apply_macro)),
+            args,
+        };
+        if let Some(eval_macro) = eval_macro {
+            expr = Expr::Call {
+                r#macro: Box::new(Expr::Macro(eval_macro)),
+                args: vec![expr],
+            };
+        }
+
+        scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("{prefix}_{}", &r#macro.name), false),
+            expr,
+            inputs: r#macro.inputs.clone(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        })
+    }
+
+    /// Creates a macro which steps a sequential macro once for every element of a sequence such
+    /// as `(a0, b0)(a1, b1)`, where each element gives the values of the `stimulus` inputs for one
+    /// cycle. It takes the outputs of the previous cycle, the remaining non-state inputs and the
+    /// sequence, and returns the outputs once the sequence is empty. Like
+    /// [`repeat_macro`](Registry::repeat_macro), each cycle needs
+    /// [`REPEAT_RESCANS`](Registry::REPEAT_RESCANS) rescans.
+    pub fn stream_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        stimulus: &[&str],
+    ) -> Result<MacroID, String> {
+        let r#macro = global_scope.get_macro(macro_id).clone();
+        let scope_id = r#macro.scope_id;
+        let macro_name = format!("STREAM_{}", &r#macro.name);
+
+        let stimulus_vars = stimulus
+            .iter()
+            .map(|name| {
+                global_scope
+                    .get_scope(scope_id)
+                    .local()
+                    .input_map
+                    .get(*name)
+                    .copied()
+                    .ok_or_else(|| format!("Macro `{}` has no input `{name}`", &r#macro.name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let obstruct_macro = Registry::obstruct_macro(global_scope);
+        let if_macro = Registry::if_macro(global_scope);
+        let unless_macro = Registry::unless_macro(global_scope);
+        let apply_macro = Registry::apply_macro(global_scope);
+        let nonempty_macro = Registry::nonempty_macro(global_scope);
+        let split_head_macro = Registry::split_head_macro(global_scope);
+
+        let layout = StateLayout::new(global_scope, macro_id);
+        let mut scope = global_scope.get_mut_scope(scope_id);
+
+        let (_, output_vars, passthrough) = Registry::feedback_vars(&mut scope, &r#macro, &layout);
+        let passthrough = passthrough
+            .into_iter()
+            .filter(|expr| !matches!(expr, Expr::Var(var) if stimulus_vars.contains(var)))
+            .collect::<Vec<_>>();
+
+        // Steps one cycle with the head of the sequence, keeping the rest of it
+        let tail = scope.new_var("tail", false, false, None);
+        let step_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("{macro_name}_STEP"), false),
+            expr: Expr::List(
+                std::iter::once(Expr::Call {
+                    r#macro: Box::new(Expr::Macro(macro_id)),
+                    args: r#macro
+                        .inputs
+                        .iter()
+                        .map(|&input| Expr::Var(input))
+                        .collect(),
+                })
+                .chain(passthrough.iter().cloned())
+                .chain(std::iter::once(Expr::Var(tail)))
+                .collect(),
+                ", ",
+            ),
+            inputs: output_vars
+                .iter()
+                .copied()
+                .chain(passthrough.iter().flat_map(Expr::vars))
+                .chain(stimulus_vars)
+                .chain(std::iter::once(tail))
+                .collect(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let indirect_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("{macro_name}_INDIRECT"), false),
+            expr: Expr::List(Vec::new(), ""),
+            inputs: Vec::new(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let seq = scope.new_var("seq", false, false, None);
+        let nonempty = Expr::Call {
+            r#macro: Box::new(Expr::Macro(nonempty_macro)),
+            args: vec![Expr::Var(seq)],
+        };
+        let stream_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&macro_name, false),
+            expr: Expr::List(
+                vec![
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(if_macro)),
+                            args: vec![nonempty.clone()],
+                        }),
+                        args: vec![Expr::Call {
+                            r#macro: Box::new(Expr::Call {
+                                r#macro: Box::new(Expr::Call {
+                                    r#macro: Box::new(Expr::Macro(obstruct_macro)),
+                                    args: vec![Expr::Macro(indirect_macro)],
+                                }),
+                                args: vec![],
+                            }),
+                            args: vec![Expr::Call {
+                                r#macro: Box::new(Expr::Macro(apply_macro)),
+                                args: std::iter::once(Expr::Macro(step_macro))
+                                    .chain(output_vars.iter().map(|&var| Expr::Var(var)))
+                                    .chain(passthrough.iter().cloned())
+                                    .chain(std::iter::once(Expr::List(
+                                        vec![Expr::Macro(split_head_macro), Expr::Var(seq)],
+                                        " ",
+                                    )))
+                                    .collect(),
+                            }],
+                        }],
+                    },
+                    Expr::Call {
+                        r#macro: Box::new(Expr::Call {
+                            r#macro: Box::new(Expr::Macro(unless_macro)),
+                            args: vec![nonempty],
+                        }),
+                        args: output_vars.iter().map(|&var| Expr::Var(var)).collect(),
+                    },
+                ],
+                " ",
+            ),
+            inputs: output_vars
+                .iter()
+                .copied()
+                .chain(passthrough.iter().flat_map(Expr::vars))
+                .chain(std::iter::once(seq))
+                .collect(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        scope.get_mut_macro(indirect_macro).expr = Expr::Macro(stream_macro);
+        Ok(stream_macro)
+    }
+
+    /// Creates a `RUN_STREAM_<name>` macro which runs a [`stream_macro`](Registry::stream_macro)
+    /// over sequences of at most `max_cycles` elements. It takes the state inputs and remaining
+    /// non-state inputs of the original macro in their original order, followed by the sequence.
+    /// Outputs which aren't state start out as `0`.
+    pub fn run_stream_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        stimulus: &[&str],
+        max_cycles: usize,
+    ) -> Result<MacroID, String> {
+        let stream_macro = Registry::stream_macro(global_scope, macro_id, stimulus)?;
+        let rescans = max_cycles * Registry::REPEAT_RESCANS;
+        let eval_macro = (rescans != 0).then(|| Registry::eval_macro(global_scope, rescans));
+
+        let r#macro = global_scope.get_macro(macro_id).clone();
+        let stream_inputs = global_scope.get_macro(stream_macro).inputs.clone();
+        let seq = *stream_inputs.last().unwrap();
+        let mut scope = global_scope.get_mut_scope(r#macro.scope_id);
+
+        let mut expr = Expr::Call {
+            r#macro: Box::new(Expr::Macro(stream_macro)),
+            args: stream_inputs
+                .iter()
+                .map(|input| {
+                    if r#macro.inputs.contains(input) || *input == seq {
+                        Expr::Var(*input)
+                    } else {
+                        Expr::Text("0".to_string())
+                    }
+                })
+                .collect(),
+        };
+        if let Some(eval_macro) = eval_macro {
+            expr = Expr::Call {
+                r#macro: Box::new(Expr::Macro(eval_macro)),
+                args: vec![expr],
+            };
+        }
+
+        Ok(scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("RUN_STREAM_{}", &r#macro.name), false),
+            expr,
+            inputs: r#macro
+                .inputs
+                .iter()
+                .copied()
+                .filter(|input| stream_inputs.contains(input))
+                .chain(std::iter::once(seq))
+                .collect(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        }))
+    }
+
+    /// Creates a `<NAME>_RUN` macro which runs module `name` from its reset state like
+    /// [`run_macro`](Registry::run_macro), so that it only takes the non-state inputs.
+    pub fn reset_run_macro(
+        global_scope: &mut GlobalScope,
+        name: &str,
+        halt: &HaltCondition,
+        max_cycles: usize,
+    ) -> Result<MacroID, String> {
+        let macro_id = Registry::module(global_scope, name)
+            .ok_or_else(|| format!("Unknown module `{name}`"))?;
+        let run_macro = Registry::run_macro(global_scope, macro_id, halt, max_cycles)?;
+        let macro_name = format!("{}_RUN", &global_scope.get_macro(macro_id).name);
+        Registry::bind_state_inputs(global_scope, name, run_macro, &macro_name, false)
+    }
+
+    /// Creates a macro which calls `entry` with the state inputs of module `name` set to their
+    /// reset values. `entry` has to take the same inputs as the module macro, like the macros from
+    /// [`cycle_macro`](Registry::cycle_macro) and [`run_macro`](Registry::run_macro) do. State
+    /// inputs without a reset value are `X` when propagating unknown values, and an error
+    /// otherwise.
+    pub fn reset_macro(
+        global_scope: &mut GlobalScope,
+        name: &str,
+        entry: MacroID,
+    ) -> Result<MacroID, String> {
+        let macro_name = format!("{}_RESET", &global_scope.get_macro(entry).name);
+        Registry::bind_state_inputs(global_scope, name, entry, &macro_name, false)
+    }
+
+    /// Creates a macro which calls `entry` like [`reset_macro`](Registry::reset_macro), but with the
+    /// state inputs of module `name` set to the `STATE_<port>` macros of a checkpoint, such as one
+    /// printed by [`checkpoint_header`](Registry::checkpoint_header).
+    pub fn resume_macro(
+        global_scope: &mut GlobalScope,
+        name: &str,
+        entry: MacroID,
+    ) -> Result<MacroID, String> {
+        let macro_name = format!("{}_RESUME", &global_scope.get_macro(entry).name);
+        Registry::bind_state_inputs(global_scope, name, entry, &macro_name, true)
+    }
+
+    /// Creates a header which prints the `STATE_<port>` macros of module `name` as `#define` lines
+    /// when it is included, for example after an
+    /// [`iteration_header`](Registry::iteration_header). Including the printed text in a later
+    /// pass resumes from the same state.
+    pub fn checkpoint_header(global_scope: &mut GlobalScope, name: &str) -> Result<String, String> {
+        let macro_id = Registry::module(global_scope, name)
+            .ok_or_else(|| format!("Unknown module `{name}`"))?;
+        let layout = StateLayout::new(global_scope, macro_id);
+
+        // Directives can't come from expansions, so the printed lines start with a macro for `#`.
+        // Each state macro is redefined around its line so that its name isn't expanded.
+        let hash = format!(
+            "{}{}H",
+            global_scope.get_alias("CHECKPOINT", true),
+            global_scope.prefix_sep()
+        );
+        let mut header = String::new();
+        writeln!(header, "#define {hash} #").unwrap();
+        for element in &layout.elements {
+            for idx in 0..element.width() {
+                let define = element.define_name(idx);
+                writeln!(
+                    header,
+                    "#ifndef {define}\n#error \"{define} is not defined\""
+                )
+                .unwrap();
+                for (directive, value) in [(format!("#elif {define}"), 1), ("#else".to_string(), 0)]
+                {
+                    writeln!(
+                        header,
+                        "{directive}\n#undef {define}\n{hash} define {define} {value}\n#define {define} {value}"
+                    )
+                    .unwrap();
+                }
+                writeln!(header, "#endif").unwrap();
+            }
+        }
+        writeln!(header, "#undef {hash}").unwrap();
+
+        Ok(header)
+    }
+
+    fn bind_state_inputs(
+        global_scope: &mut GlobalScope,
+        name: &str,
+        entry: MacroID,
+        macro_name: &str,
+        resume: bool,
+    ) -> Result<MacroID, String> {
+        let macro_id = Registry::module(global_scope, name)
+            .ok_or_else(|| format!("Unknown module `{name}`"))?;
+        let module = global_scope.registry().modules.get(name).unwrap();
+        let x_propagation = global_scope.registry().x_propagation;
+        let module_inputs = global_scope.get_macro(macro_id).inputs.clone();
+        let entry_macro = global_scope.get_macro(entry).clone();
+
+        let mut args = entry_macro
+            .inputs
+            .iter()
+            .map(|&input| Expr::Var(input))
+            .collect::<Vec<_>>();
+        for element in StateLayout::new(global_scope, macro_id).elements {
+            for (idx, &position) in element.input_positions.iter().enumerate() {
+                let port_name = element.input_port(idx);
+                let value = match module.reset_values.get(&port_name) {
+                    _ if resume => element.define_name(idx),
+                    Some(value) => (*value as u8).to_string(),
+                    None if x_propagation => "X".to_string(),
+                    None => {
+                        return Err(format!(
+                            "State input `{port_name}` of module `{name}` has no reset value"
+                        ));
+                    }
+                };
+
+                let arg = entry_macro
+                    .inputs
+                    .iter()
+                    .position(|&input| input == module_inputs[position])
+                    .ok_or_else(|| {
+                        format!("Macro `{}` has no input `{port_name}`", entry_macro.name)
+                    })?;
+                args[arg] = Expr::Text(value);
+            }
+        }
+
+        let inputs = args
+            .iter()
+            .filter_map(|arg| {
+                match arg {
+                    Expr::Var(var) => Some(*var),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let mut scope = global_scope.get_mut_scope(entry_macro.scope_id);
+        Ok(scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(macro_name, false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(entry)),
+                args,
+            },
+            inputs,
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        }))
+    }
+
+    /// Creates a macro which advances the state of a sequential macro by exactly `cycles` clock
+    /// cycles. It takes the same inputs as the original macro and returns its outputs from the
+    /// last cycle. The cycles are unrolled with nested calls, so no `EVAL` is needed.
+    pub fn cycle_macro(
+        global_scope: &mut GlobalScope,
+        macro_id: MacroID,
+        cycles: usize,
+    ) -> MacroID {
+        assert_ne!(cycles, 0);
+
+        let r#macro = global_scope.get_macro(macro_id).clone();
+        let scope_id = r#macro.scope_id;
+        let macro_name = format!("CYCLES{cycles}_{}", &r#macro.name);
+
+        let apply_macro = Registry::apply_macro(global_scope);
+
+        let layout = StateLayout::new(global_scope, macro_id);
+        let mut scope = global_scope.get_mut_scope(scope_id);
+        let (step_inputs, output_vars, passthrough) =
+            Registry::feedback_vars(&mut scope, &r#macro, &layout);
+
+        let call = Expr::Call {
+            r#macro: Box::new(Expr::Macro(macro_id)),
+            args: r#macro
+                .inputs
+                .iter()
+                .map(|&input| Expr::Var(input))
+                .collect(),
+        };
+
+        // Advances one cycle, keeping the passthrough inputs so it can be chained
+        let step_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("{macro_name}_STEP"), false),
+            expr: Expr::List(
+                std::iter::once(call.clone())
+                    .chain(passthrough.iter().cloned())
+                    .collect(),
+                ", ",
+            ),
+            inputs: step_inputs.clone(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        let last_macro = scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&format!("{macro_name}_LAST"), false),
+            expr: call,
+            inputs: step_inputs.clone(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        });
+
+        // Macros stepping `2^idx` cycles
+        let mut pow2_macros = Vec::<MacroID>::new();
+        for idx in 0..usize::BITS - (cycles - 1).leading_zeros() {
+            let variadic = scope.new_var("variadic", false, true, None);
+            let expr = if let Some(&prev) = pow2_macros.last() {
+                Expr::Call {
+                    r#macro: Box::new(Expr::Macro(prev)),
+                    args: vec![Expr::Call {
+                        r#macro: Box::new(Expr::Macro(prev)),
+                        args: vec![Expr::Var(variadic)],
+                    }],
+                }
+            } else {
+                Expr::Call {
+                    r#macro: Box::new(Expr::Macro(apply_macro)),
+                    args: vec![Expr::Macro(step_macro), Expr::Var(variadic)],
+                }
+            };
+
+            pow2_macros.push(scope.new_macro(Macro {
+                scope_id: scope.id,
+                name: scope.get_alias(&format!("{macro_name}_X{}", 1_usize << idx), false),
+                expr,
+                inputs: vec![variadic],
+                variadicified_vars: None,
+                calling_split: None,
+                doc_name: None,
+            }));
+        }
+
+        // State outputs start from the state inputs, the other outputs aren't used by the macro
+        let mut expr = Expr::List(
+            output_vars
+                .iter()
+                .enumerate()
+                .map(|(position, var)| {
+                    if layout.is_state_output(position) {
+                        Expr::Var(*var)
+                    } else {
+                        Expr::Text("0".to_string())
+                    }
+                })
+                .chain(passthrough)
+                .collect(),
+            ", ",
+        );
+        for (idx, &pow2_macro) in pow2_macros.iter().enumerate() {
+            if (cycles - 1) & (1 << idx) != 0 {
+                expr = Expr::Call {
+                    r#macro: Box::new(Expr::Macro(pow2_macro)),
+                    args: vec![expr],
+                };
+            }
+        }
+
+        scope.new_macro(Macro {
+            scope_id: scope.id,
+            name: scope.get_alias(&macro_name, false),
+            expr: Expr::Call {
+                r#macro: Box::new(Expr::Macro(apply_macro)),
+                args: vec![Expr::Macro(last_macro), expr],
+            },
+            inputs: r#macro.inputs.clone(),
+            variadicified_vars: None,
+            calling_split: None,
+            doc_name: None,
+        })
+    }
+
+    /// Creates a header which runs module `name` for `cycles` clock cycles when it is included, or
+    /// until its outputs meet the `halt` condition. Like file iteration in Boost.PP, the header
+    /// includes itself by its `file_name` once per cycle, nesting one level per decimal digit of
+    /// `cycles`, so the cost grows linearly with the cycle count instead of being limited by
+    /// rescans.
+    ///
+    /// The state is kept in `STATE_<port>` macros, which start from the reset values unless they
+    /// are already defined. Non-state inputs are read from `INPUT_<port>` macros, and the other
+    /// outputs of the last cycle are left in `OUTPUT_<port>` macros. Values are read with `#if`,
+    /// so they have to be `0` or `1`.
+    pub fn iteration_header(
+        global_scope: &mut GlobalScope,
+        name: &str,
+        file_name: &str,
+        cycles: usize,
+        halt: Option<&HaltCondition>,
+    ) -> Result<String, String> {
+        let macro_id = Registry::module(global_scope, name)
+            .ok_or_else(|| format!("Unknown module `{name}`"))?;
+        let r#macro = global_scope.get_macro(macro_id).clone();
+        let output_names = global_scope
+            .get_scope(r#macro.scope_id)
+            .local()
+            .output_names
+            .clone()
+            .unwrap();
+        let layout = StateLayout::new(global_scope, macro_id);
+
+        let mut args = r#macro
+            .input_names(global_scope)
+            .into_iter()
+            .map(|input| format!("INPUT_{}", port_identifier(&input.unwrap())))
+            .collect::<Vec<_>>();
+        let mut outputs = output_names
+            .iter()
+            .map(|output| format!("OUTPUT_{}", port_identifier(output)))
+            .collect::<Vec<_>>();
+        for element in &layout.elements {
+            for idx in 0..element.width() {
+                args[element.input_positions[idx]] = element.define_name(idx);
+                outputs[element.output_positions[idx]] = element.define_name(idx);
+            }
+        }
+
+        let halt_macro = match halt {
+            Some(halt) => {
+                let halt_outputs =
+                    halt_positions(global_scope.registry(), halt, &output_names, &r#macro.name)?;
+                let lut = halt.lut(&format!("ITERATE_{}_HALT", &r#macro.name));
+                let halt_macro = lut.make_macro(global_scope);
+                let mut positions = lut
+                    .input_names
+                    .iter()
+                    .zip(halt_outputs)
+                    .map(|(name, output)| {
+                        (
+                            global_scope
+                                .get_macro(halt_macro)
+                                .input_position(name, global_scope)
+                                .unwrap(),
+                            output,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                positions.sort_by_key(|(idx, _)| *idx);
+                Some((halt_macro, positions))
+            }
+            None => None,
+        };
+
+        let apply_macro = Registry::apply_macro(global_scope);
+        let pick_macros = (0..output_names.len())
+            .map(|idx| Registry::pick_macro(global_scope, idx))
+            .collect::<Vec<_>>();
+
+        let prefix = global_scope.get_alias("ITERATE", true);
+
+        let sep = global_scope.prefix_sep();
+        let depth = format!("{prefix}{sep}D");
+        let halted = format!("{prefix}{sep}H");
+        let next = format!("{prefix}{sep}N");
+        let temp = |idx: usize| format!("{prefix}{sep}{idx}");
+        let include = |level: usize| {
+            format!("#undef {depth}\n#define {depth} {level}\n#include \"{file_name}\"\n")
+        };
+
+        let mut digits = Vec::new();
+        let mut remaining = cycles;
+        while remaining != 0 {
+            digits.push(remaining % 10);
+            remaining /= 10;
+        }
+
+        // Setup when included from outside, which then runs the cycles from the highest digit down
+        let mut header = String::new();
+        writeln!(header, "#ifndef {depth}").unwrap();
+        header.push_str(&global_scope.emit());
+
+        let module = global_scope.registry().modules.get(name).unwrap();
+        for element in &layout.elements {
+            for idx in 0..element.width() {
+                let define = element.define_name(idx);
+                writeln!(header, "#ifndef {define}").unwrap();
+                match module.reset_values.get(&element.input_port(idx)) {
+                    Some(value) => writeln!(header, "#define {define} {}", *value as u8),
+                    None => writeln!(header, "#error \"{define} has no reset value\""),
+                }
+                .unwrap();
+                writeln!(header, "#endif").unwrap();
+            }
+        }
+        for (position, arg) in args.iter().enumerate() {
+            if !layout.is_state_input(position) {
+                writeln!(
+                    header,
+                    "#ifndef {arg}\n#error \"{arg} is not defined\"\n#endif"
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(header, "#undef {halted}").unwrap();
+        for (level, &digit) in digits.iter().enumerate().rev() {
+            for _ in 0..digit {
+                header.push_str(&include(level));
+            }
+        }
+        writeln!(header, "#undef {depth}").unwrap();
+
+        // A single cycle, where the outputs are read into temporaries before the state is replaced
+        writeln!(header, "#elif {depth} == 0\n#ifndef {halted}").unwrap();
+        writeln!(
+            header,
+            "#define {next} {}({})",
+            &r#macro.name,
+            args.join(", ")
+        )
+        .unwrap();
+        for (idx, &pick_macro) in pick_macros.iter().enumerate() {
+            writeln!(
+                header,
+                "#if {}({}, {next})\n#define {} 1\n#else\n#define {} 0\n#endif",
+                global_scope.get_macro(apply_macro).name,
+                global_scope.get_macro(pick_macro).name,
+                temp(idx),
+                temp(idx)
+            )
+            .unwrap();
+        }
+        if let Some((halt_macro, positions)) = halt_macro {
+            writeln!(
+                header,
+                "#if !{}({})\n#define {halted}\n#endif",
+                global_scope.get_macro(halt_macro).name,
+                positions
+                    .into_iter()
+                    .map(|(_, output)| temp(output))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .unwrap();
+        }
+        for (idx, output) in outputs.iter().enumerate() {
+            writeln!(
+                header,
+                "#undef {output}\n#if {}\n#define {output} 1\n#else\n#define {output} 0\n#endif\n#undef {}",
+                temp(idx),
+                temp(idx)
+            )
+            .unwrap();
+        }
+        writeln!(header, "#undef {next}\n#endif").unwrap();
+
+        // Each higher level runs ten of the level below it
+        for level in 1..digits.len() {
+            writeln!(header, "#elif {depth} == {level}").unwrap();
+            for _ in 0..10 {
+                header.push_str(&include(level - 1));
+            }
+        }
+        writeln!(header, "#endif").unwrap();
+
+        Ok(header)
+    }
+
+    /// Inputs for macros which feed the outputs of a sequential macro back into it. Outputs with
+    /// a matching state input use its var, other outputs get new vars. These are followed by the
+    /// remaining inputs, which are passed through unchanged.
+    fn feedback_vars(
+        scope: &mut MutScope,
+        r#macro: &Macro,
+        layout: &StateLayout,
+    ) -> (Vec<VarID>, Vec<VarID>, Vec<Expr>) {
+        let mut output_vars = Vec::new();
+        let output_names = scope.local().output_names.clone().unwrap();
+        for (position, output) in output_names.iter().enumerate() {
+            match layout.feedback().find(|(output, _)| *output == position) {
+                Some((_, input)) => output_vars.push(r#macro.inputs[input]),
+                None => output_vars.push(scope.new_var(output, false, false, None)),
+            }
+        }
+
+        let mut inputs = output_vars.clone();
+        let mut passthrough = Vec::new();
+        for &input in &r#macro.inputs {
+            if !inputs.contains(&input) {
+                inputs.push(input);
+                passthrough.push(Expr::Var(input));
+            }
+        }
+
+        (inputs, output_vars, passthrough)
+    }
+
+    /// Evaluates a lut or module with the built-in simulator. Inputs and outputs are keyed by port
+    /// name, and their values are indices into the alphabet of the luts. With `propagate_x`,
+    /// binary luts also take `X` (2), which undriven bits carry. Returns `None` if there is no lut
+    /// or module called `name`, or if an input is missing.
+    pub fn evaluate(
+        &self,
+        name: &str,
+        inputs: &HashMap<String, usize>,
+    ) -> Option<HashMap<String, usize>> {
+        if let Some(lut) = self.luts.get(name) {
+            let values = lut
+                .input_names
+                .iter()
+                .map(|name| inputs.get(name).copied())
+                .collect::<Option<Vec<_>>>()?;
+
+            let outputs = if self.x_propagation && lut.is_binary() {
+                lut.eval_x(&values)
+            } else {
+                lut.eval(&values).to_vec()
+            };
+
+            return Some(lut.output_names.iter().cloned().zip(outputs).collect());
+        }
+
+        let module = self.modules.get(name)?;
+        let mut drivers = HashMap::new();
+        for cell in module.cells.values() {
+            for (_, wire) in cell.output_connections() {
+                drivers.insert(wire, cell);
+            }
+        }
+
+        let mut values = module
+            .input_ports()
+            .map(|(name, port)| Some((port.wire, *inputs.get(name)?)))
+            .collect::<Option<HashMap<_, _>>>()?;
+
+        Some(
+            module
+                .output_ports()
+                .map(|(name, port)| {
+                    (
+                        name.clone(),
+                        self.evaluate_wire(port.wire, &drivers, &mut values),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn evaluate_wire(
+        &self,
+        wire: Wire,
+        drivers: &HashMap<Wire, &Cell>,
+        values: &mut HashMap<Wire, usize>,
+    ) -> usize {
+        if let Some(&value) = values.get(&wire) {
+            return value;
+        }
+
+        match wire {
+            Wire::Const(constant) => return constant as usize,
+            Wire::Undef => return if self.x_propagation { 2 } else { 0 },
+            Wire::Wire(_) => {}
+        }
+
+        let cell = drivers.get(&wire).unwrap();
+        let cell_inputs = cell
+            .input_connections()
+            .map(|(name, input)| (name.clone(), self.evaluate_wire(input, drivers, values)))
+            .collect();
+        let cell_outputs = self
+            .evaluate(&cell.kind, &cell_inputs)
+            .unwrap_or_else(|| panic!("Unknown cell type `{}`", &cell.kind));
+
+        for (name, output) in cell.output_connections() {
+            values.insert(output, *cell_outputs.get(name).unwrap());
+        }
+
+        *values.get(&wire).unwrap()
+    }
+
+    fn lut_variant(&self, lut: Lut) -> Lut {
+        if self.x_propagation && lut.is_binary() {
+            lut.extend_x()
+        } else {
+            lut
+        }
+    }
+
+    fn collapse_module(&self, name: &str, module: &Module) -> Lut {
+        let input_names = module
+            .input_ports()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let output_names = module
+            .output_ports()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+
+        // Undriven bits can only be kept as `X` by a table which takes `X` inputs as well
+        let alphabet: &[&str] = if self.x_propagation {
+            &["0", "1", "X"]
+        } else {
+            &["0", "1"]
+        };
+
+        Lut::from_fn(
+            name,
+            &input_names,
+            &output_names,
+            alphabet,
+            &vec![alphabet.len(); input_names.len()],
+            |inputs| {
+                let values = self
+                    .evaluate(
+                        name,
+                        &input_names
+                            .iter()
+                            .map(|name| name.to_string())
+                            .zip(inputs.iter().copied())
+                            .collect(),
+                    )
+                    .unwrap();
+                output_names
+                    .iter()
+                    .map(|output| {
+                        // Undriven bits evaluate to 0 without `propagate_x`, as in
+                        // `create_module`, so only multi-valued luts can leave the alphabet
+                        let value = *values.get(*output).unwrap();
+                        assert!(
+                            value < alphabet.len(),
+                            "Output `{output}` of `{name}` takes a value outside the binary \
+                             alphabet, so the module can't be collapsed"
+                        );
+                        value
+                    })
+                    .collect()
+            },
+        )
+    }
+
+    fn name_available(&self, name: &str) -> bool {
+        !self
+            .luts
+            .keys()
+            .chain(self.modules.keys())
+            .any(|existing| existing == name)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+            .register_lut(Lut::not())
+            .register_lut(Lut::or())
+            .register_lut(Lut::and())
+            .register_lut(Lut::xor())
+            .register_lut(Lut::mux())
+    }
+}
+
+/// Positions of the outputs which a halt condition depends on
+fn halt_positions(
+    registry: &Registry,
+    halt: &HaltCondition,
+    output_names: &[String],
+    macro_name: &str,
+) -> Result<Vec<usize>, String> {
+    // An `X` output would paste into a row or branch which doesn't exist and end the loop
+    if registry.x_propagation() {
+        return Err(format!(
+            "Halt condition for macro `{macro_name}` can't take `X` outputs with `propagate_x`"
+        ));
+    }
+
+    let outputs = halt.outputs();
+    if outputs.is_empty() {
+        return Err(format!(
+            "Halt condition for macro `{macro_name}` depends on no outputs"
+        ));
+    }
+
+    outputs
+        .into_iter()
+        .map(|name| output_position(output_names, name, macro_name))
+        .collect()
+}
+
+fn output_position(output_names: &[String], name: &str, macro_name: &str) -> Result<usize, String> {
+    output_names
+        .iter()
+        .position(|output| output == name)
+        .ok_or_else(|| format!("Macro `{macro_name}` has no output `{name}`"))
 }
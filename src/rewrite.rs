@@ -0,0 +1,274 @@
+use rand::Rng;
+use rand::seq::IndexedRandom;
+
+use crate::netlist::{Cell, Module, NetBit, PortDir, Wire};
+use crate::{Map, Set};
+
+const NOT: &str = "$_NOT_";
+const AND: &str = "$_AND_";
+const OR: &str = "$_OR_";
+const XOR: &str = "$_XOR_";
+const MUX: &str = "$_MUX_";
+
+/// Replaces gates with equivalent compositions, re-associates chains of gates and inserts decoy
+/// cells whose outputs cancel out. Each rewrite happens with a chance of `intensity`, and only
+/// uses the gates in `binary_luts`, whose cells are the ones that get decoys. With `x_safe`, only
+/// rewrites which also keep the result of `X` inputs are used.
+pub fn rewrite_module<R: Rng>(
+    module: &Module,
+    binary_luts: &Set<String>,
+    intensity: f64,
+    x_safe: bool,
+    rng: &mut R,
+) -> Module {
+    assert!((0.0..=1.0).contains(&intensity));
+
+    let mut rewriter = Rewriter::new(module, binary_luts, rng);
+    rewriter.reassociate(intensity);
+    rewriter.replace_gates(intensity, x_safe);
+    if !x_safe {
+        rewriter.insert_decoys(intensity);
+    }
+
+    rewriter.module
+}
+
+struct Rewriter<'a, R> {
+    module: Module,
+    binary_luts: &'a Set<String>,
+    next_wire: usize,
+    next_cell: usize,
+    rng: &'a mut R,
+}
+
+impl<'a, R: Rng> Rewriter<'a, R> {
+    fn new(module: &Module, binary_luts: &'a Set<String>, rng: &'a mut R) -> Self {
+        let next_wire = module
+            .cells
+            .values()
+            .flat_map(|cell| cell.connections.values().copied())
+            .chain(module.ports.values().map(|port| port.wire))
+            .filter_map(|wire| {
+                match wire {
+                    Wire::Wire(id) => Some(id + 1),
+                    _ => None,
+                }
+            })
+            .chain(module.netnames.values().flat_map(|netname| {
+                netname.bits.iter().filter_map(|bit| {
+                    match bit {
+                        NetBit::Wire(id) => Some(id + 1),
+                        NetBit::Const(_) => None,
+                    }
+                })
+            }))
+            .max()
+            .unwrap_or_default();
+
+        Self {
+            module: module.clone(),
+            binary_luts,
+            next_wire,
+            next_cell: 0,
+            rng,
+        }
+    }
+
+    /// Turns `op(op(x, y), z)` into `op(x, op(y, z))` where the inner gate has no other consumers
+    fn reassociate(&mut self, intensity: f64) {
+        let mut consumers = Map::new();
+        for wire in self
+            .module
+            .cells
+            .values()
+            .flat_map(|cell| cell.input_connections().map(|(_, wire)| wire))
+            .chain(self.module.output_ports().map(|(_, port)| port.wire))
+        {
+            *consumers.entry(wire).or_insert(0_usize) += 1;
+        }
+        let drivers = self
+            .module
+            .cells
+            .iter()
+            .flat_map(|(name, cell)| {
+                cell.output_connections()
+                    .map(move |(_, wire)| (wire, name.clone()))
+            })
+            .collect::<Map<_, _>>();
+
+        for outer_name in self.module.cells.keys().cloned().collect::<Vec<_>>() {
+            let outer = self.module.cells.get(&outer_name).unwrap();
+            if ![AND, OR, XOR].contains(&outer.kind.as_str()) || !self.rng.random_bool(intensity) {
+                continue;
+            }
+
+            let (inner_port, other_port) = if self.rng.random() {
+                ("A", "B")
+            } else {
+                ("B", "A")
+            };
+            let inner_wire = *outer.connections.get(inner_port).unwrap();
+            let other_wire = *outer.connections.get(other_port).unwrap();
+            let Some(inner_name) = drivers.get(&inner_wire) else {
+                continue;
+            };
+            let inner = self.module.cells.get(inner_name).unwrap();
+            if inner.kind != outer.kind || consumers.get(&inner_wire) != Some(&1) {
+                continue;
+            }
+
+            let x = *inner.connections.get("A").unwrap();
+            let y = *inner.connections.get("B").unwrap();
+            let inner_name = inner_name.clone();
+
+            let inner = self.module.cells.get_mut(&inner_name).unwrap();
+            inner.connections.insert("A".to_string(), y);
+            inner.connections.insert("B".to_string(), other_wire);
+
+            let outer = self.module.cells.get_mut(&outer_name).unwrap();
+            outer.connections.insert("A".to_string(), x);
+            outer.connections.insert("B".to_string(), inner_wire);
+        }
+    }
+
+    fn replace_gates(&mut self, intensity: f64, x_safe: bool) {
+        for name in self.module.cells.keys().cloned().collect::<Vec<_>>() {
+            let cell = self.module.cells.get(&name).unwrap();
+            let needed: &[&str] = match cell.kind.as_str() {
+                NOT => &[XOR],
+                AND => &[NOT, OR],
+                OR => &[NOT, AND],
+                XOR => &[NOT, AND, OR],
+                MUX if !x_safe => &[NOT, AND, OR],
+                _ => continue,
+            };
+            if !self.available(needed) || !self.rng.random_bool(intensity) {
+                continue;
+            }
+
+            let cell = self.module.cells.remove(&name).unwrap();
+            let input = |port: &str| *cell.connections.get(port).unwrap();
+            let output = input("Y");
+
+            match cell.kind.as_str() {
+                NOT => self.add_cell(XOR, &[input("A"), Wire::Const(true)], output),
+                AND | OR => {
+                    // De Morgan
+                    let dual = if cell.kind == AND { OR } else { AND };
+                    let a = self.gate(NOT, &[input("A")]);
+                    let b = self.gate(NOT, &[input("B")]);
+                    let inner = self.gate(dual, &[a, b]);
+                    self.add_cell(NOT, &[inner], output);
+                }
+                XOR => {
+                    let either = self.gate(OR, &[input("A"), input("B")]);
+                    let both = self.gate(AND, &[input("A"), input("B")]);
+                    let not_both = self.gate(NOT, &[both]);
+                    self.add_cell(AND, &[either, not_both], output);
+                }
+                MUX => {
+                    let not_select = self.gate(NOT, &[input("S")]);
+                    let a = self.gate(AND, &[not_select, input("A")]);
+                    let b = self.gate(AND, &[input("S"), input("B")]);
+                    self.add_cell(OR, &[a, b], output);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Routes gate inputs through pairs of cells which cancel out, using the module inputs
+    fn insert_decoys(&mut self, intensity: f64) {
+        let inputs = self
+            .module
+            .input_ports()
+            .map(|(_, port)| port.wire)
+            .filter(|wire| matches!(wire, Wire::Wire(_)))
+            .collect::<Vec<_>>();
+        let masks = self.available(&[XOR]);
+        let zeros = self.available(&[NOT, AND, OR]);
+        if inputs.is_empty() || !(masks || zeros) {
+            return;
+        }
+
+        for name in self.module.cells.keys().cloned().collect::<Vec<_>>() {
+            let cell = self.module.cells.get(&name).unwrap();
+            if !self.binary_luts.contains(&cell.kind) {
+                continue;
+            }
+
+            for (port, wire) in cell
+                .input_connections()
+                .map(|(port, wire)| (port.clone(), wire))
+                .collect::<Vec<_>>()
+            {
+                if !self.rng.random_bool(intensity / 2.0) {
+                    continue;
+                }
+
+                let other = *inputs.choose(&mut *self.rng).unwrap();
+                let decoy = if masks && (!zeros || self.rng.random()) {
+                    let masked = self.gate(XOR, &[wire, other]);
+                    self.gate(XOR, &[masked, other])
+                } else {
+                    let not_other = self.gate(NOT, &[other]);
+                    let zero = self.gate(AND, &[other, not_other]);
+                    self.gate(OR, &[wire, zero])
+                };
+
+                self.module
+                    .cells
+                    .get_mut(&name)
+                    .unwrap()
+                    .connections
+                    .insert(port, decoy);
+            }
+        }
+    }
+
+    fn available(&self, gates: &[&str]) -> bool {
+        gates.iter().all(|gate| self.binary_luts.contains(*gate))
+    }
+
+    /// Adds a gate driving a new wire
+    fn gate(&mut self, kind: &str, inputs: &[Wire]) -> Wire {
+        let output = Wire::Wire(self.next_wire);
+        self.next_wire += 1;
+        self.add_cell(kind, inputs, output);
+        output
+    }
+
+    fn add_cell(&mut self, kind: &str, inputs: &[Wire], output: Wire) {
+        let ports: &[&str] = match kind {
+            NOT => &["A"],
+            MUX => &["S", "A", "B"],
+            _ => &["A", "B"],
+        };
+        assert_eq!(ports.len(), inputs.len());
+
+        let mut name;
+        while {
+            name = format!("$rewrite${}", self.next_cell);
+            self.next_cell += 1;
+            self.module.cells.contains_key(&name)
+        } {}
+
+        let mut port_dirs = Map::new();
+        let mut connections = Map::new();
+        for (&port, &wire) in ports.iter().zip(inputs) {
+            port_dirs.insert(port.to_string(), PortDir::Input);
+            connections.insert(port.to_string(), wire);
+        }
+        port_dirs.insert("Y".to_string(), PortDir::Output);
+        connections.insert("Y".to_string(), output);
+
+        self.module.cells.insert(
+            name,
+            Cell {
+                kind: kind.to_string(),
+                port_dirs,
+                connections,
+            },
+        );
+    }
+}
@@ -1,40 +1,62 @@
-use std::collections::HashMap;
+use std::cell::{RefCell, RefMut};
 use std::fmt::{self, Display};
 
 use colored::Colorize;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
 use crate::r#macro::{Macro, MacroID};
+use crate::obfuscation::{KeyEntry, ObfuscationConfig, ObfuscationKey};
 use crate::registry::Registry;
 use crate::scope::local::{LocalScope, LocalScopeID};
 use crate::scope::{MutScope, Scope};
+use crate::state::StateLayout;
 use crate::{Map, Set};
 
 pub struct GlobalScope {
     registry: Registry,
+    obfuscation: ObfuscationConfig,
 
     next_scope_id: LocalScopeID,
     next_macro_id: MacroID,
 
-    pub scopes: HashMap<LocalScopeID, LocalScope>,
+    pub scopes: Map<LocalScopeID, LocalScope>,
     macros: Map<MacroID, Macro>,
     defines: Map<String, String>,
 
+    rng: RefCell<SmallRng>,
+    display_seed: u64,
     prefix_capitalization: Vec<bool>,
+    /// Readable names of the obfuscated aliases
+    aliases: RefCell<Map<String, KeyEntry>>,
+    /// Readable names of the table prefixes, by the length of their aliases
+    tables: RefCell<Map<usize, Map<String, KeyEntry>>>,
 }
 
 impl GlobalScope {
-    pub fn new(registry: Registry) -> Self {
+    pub fn new(registry: Registry, obfuscation: ObfuscationConfig) -> Self {
+        let mut rng = obfuscation.rng();
+        let display_seed = rng.random();
+        let prefix_capitalization = if obfuscation.enabled {
+            (0..26).map(|_| rng.random()).collect()
+        } else {
+            vec![false; 26]
+        };
+
         Self {
             registry,
+            obfuscation,
             next_scope_id: LocalScopeID(0),
             next_macro_id: MacroID(0),
-            scopes: HashMap::new(),
+            scopes: Map::new(),
             macros: Map::new(),
             defines: Map::new(),
-            #[cfg(feature = "obfuscate")]
-            prefix_capitalization: rand::random_iter().take(26).collect(),
-            #[cfg(not(feature = "obfuscate"))]
-            prefix_capitalization: vec![false; 26],
+            rng: RefCell::new(rng),
+            display_seed,
+            prefix_capitalization,
+            aliases: RefCell::new(Map::new()),
+            tables: RefCell::new(Map::new()),
         }
     }
 
@@ -46,12 +68,33 @@ impl GlobalScope {
         &mut self.registry
     }
 
+    pub fn obfuscation(&self) -> &ObfuscationConfig {
+        &self.obfuscation
+    }
+
+    /// Source of the random choices, which is seeded by the `ObfuscationConfig`
+    pub fn rng(&self) -> RefMut<'_, SmallRng> {
+        self.rng.borrow_mut()
+    }
+
+    pub fn prefix_sep(&self) -> &'static str {
+        self.obfuscation.prefix_sep()
+    }
+
+    pub fn state_layout(&self, macro_id: MacroID) -> StateLayout {
+        StateLayout::new(self, macro_id)
+    }
+
     pub fn new_scope<'a>(&'a mut self) -> MutScope<'a> {
         let id = self.next_scope_id;
         self.next_scope_id.0 += 1;
 
+        let rng = self
+            .obfuscation
+            .enabled
+            .then(|| SmallRng::from_rng(&mut *self.rng.borrow_mut()));
         self.scopes
-            .insert(id, LocalScope::new(self.prefix_capitalization.clone()));
+            .insert(id, LocalScope::new(rng, self.prefix_capitalization.clone()));
         self.get_mut_scope(id)
     }
 
@@ -87,39 +130,137 @@ impl GlobalScope {
 
     pub fn define(&mut self, key: String, value: String) {
         assert!(self.name_available(&key, false));
+
+        if self.obfuscation.enabled {
+            let tables = self.tables.borrow();
+            let table = tables
+                .iter()
+                .filter_map(|(&len, prefixes)| Some((len, prefixes.get(key.get(..len)?)?)))
+                .max_by_key(|(len, _)| *len);
+            if let Some((len, table)) = table {
+                let entry = KeyEntry {
+                    name: format!("{}{}", table.name, &key[len..]),
+                    origin: format!("row of {}", table.origin),
+                };
+                self.aliases.borrow_mut().insert(key.clone(), entry);
+            }
+        }
+
         self.defines.insert(key, value);
     }
 
-    #[cfg(not(feature = "obfuscate"))]
     pub fn get_alias(&self, name: &str, prefix: bool) -> String {
-        let mut alias;
-        let mut suffix = None;
+        if !self.obfuscation.enabled {
+            return readable_alias(name, |alias| !self.name_available(alias, prefix));
+        }
 
-        while {
-            alias = if let Some(suffix) = suffix {
-                format!("{}_{suffix}", preprocess_macro_name(name))
-            } else {
-                preprocess_macro_name(name)
-            };
+        // The key gets the name which a readable scope would have chosen
+        let readable = readable_alias(name, |alias| {
+            self.aliases.borrow().values().any(|entry| {
+                if prefix {
+                    entry.name.starts_with(alias)
+                } else {
+                    entry.name == alias
+                }
+            })
+        });
+        let entry = if prefix {
+            KeyEntry {
+                name: format!("{readable}_"),
+                origin: format!("table `{name}`"),
+            }
+        } else {
+            KeyEntry {
+                name: readable,
+                origin: format!("`{name}`"),
+            }
+        };
+
+        let alias = self.random_alias(prefix);
+        if prefix {
+            self.tables
+                .borrow_mut()
+                .entry(alias.len())
+                .or_default()
+                .insert(alias.clone(), entry.clone());
+        }
+        self.aliases.borrow_mut().insert(alias.clone(), entry);
+        alias
+    }
 
-            !self.name_available(&alias, prefix)
-        } {
-            suffix = Some(suffix.map_or(0, |x| x + 1));
+    /// Key for the names chosen so far, where module macros are described by their split
+    pub fn obfuscation_key(&self) -> ObfuscationKey {
+        let mut key = ObfuscationKey::default();
+
+        for (alias, entry) in self.aliases.borrow().iter() {
+            let mut entry = entry.clone();
+            if let Some(r#macro) = self.macros.values().find(|r#macro| r#macro.name == *alias) {
+                let mut split = 0;
+                let mut first = r#macro;
+                while let Some(caller) = first.calling_split {
+                    first = self.get_macro(caller);
+                    split += 1;
+                }
+                if let Some(doc_name) = &first.doc_name {
+                    entry.origin = format!("split {split} of `{doc_name}`");
+                }
+
+                let scope = self.scopes.get(&r#macro.scope_id).unwrap();
+                key.params.insert(
+                    alias.clone(),
+                    r#macro
+                        .inputs
+                        .iter()
+                        .filter_map(|&var| {
+                            Some((
+                                scope.get_var(var).name.clone(),
+                                scope.key_entry(var)?.clone(),
+                            ))
+                        })
+                        .collect(),
+                );
+            }
+
+            key.names.insert(alias.clone(), entry);
         }
 
-        alias
+        key
     }
 
-    #[cfg(feature = "obfuscate")]
-    pub fn get_alias(&self, _name: &str, prefix: bool) -> String {
-        const TRIES_PER_LENGTH: usize = 1024;
+    /// Random encoding for the values of a wire, which is either the alphabet with `0` and `1`
+    /// swapped or reserved names of equal length
+    pub fn wire_encoding(&self, alphabet: &[String], origin: &str) -> Vec<String> {
+        assert!(self.obfuscation.enabled);
+
+        if self.rng.borrow_mut().random() {
+            let mut tokens = alphabet.to_vec();
+            tokens.swap(0, 1);
+            return tokens;
+        }
+
+        let tokens = self.random_aliases(alphabet.len(), false);
+        for (token, value) in tokens.iter().zip(alphabet) {
+            let entry = KeyEntry {
+                name: value.clone(),
+                origin: format!("`{value}` on {origin}"),
+            };
+            self.aliases.borrow_mut().insert(token.clone(), entry);
+        }
+        tokens
+    }
+
+    fn random_alias(&self, prefix: bool) -> String {
+        self.random_aliases(1, prefix).pop().unwrap()
+    }
 
-        use rand::rngs::SmallRng;
-        use rand::{Rng, SeedableRng};
+    /// Distinct aliases of equal length
+    fn random_aliases(&self, count: usize, prefix: bool) -> Vec<String> {
+        const TRIES_PER_LENGTH: usize = 1024;
 
-        let mut rng = SmallRng::from_os_rng();
+        let mut rng = self.rng.borrow_mut();
 
         for len in 1.. {
+            let mut aliases = Vec::new();
             for _ in 0..TRIES_PER_LENGTH {
                 let mut alias = String::with_capacity(len);
                 let idx = rng.random_range(0..26);
@@ -144,8 +285,21 @@ impl GlobalScope {
                     );
                 }
 
-                if self.name_available(&alias, prefix) {
-                    return alias;
+                // Wire tokens aren't defined, but still mustn't start a table row
+                if self.name_available(&alias, prefix)
+                    && !self.aliases.borrow().keys().any(|existing| {
+                        if prefix {
+                            existing.starts_with(&alias)
+                        } else {
+                            *existing == alias
+                        }
+                    })
+                    && !aliases.contains(&alias)
+                {
+                    aliases.push(alias);
+                    if aliases.len() == count {
+                        return aliases;
+                    }
                 }
             }
         }
@@ -193,13 +347,18 @@ impl Display for GlobalScope {
         }
 
         for r#macro in self.macros.values() {
-            lines.insert(if cfg!(feature = "obfuscate") {
+            lines.insert(if self.obfuscation.enabled {
                 r#macro.emit(self).replace(", ", ",")
             } else {
                 r#macro.emit(self)
             });
         }
 
+        let mut lines = lines.into_iter().collect::<Vec<_>>();
+        if self.obfuscation.enabled {
+            lines.shuffle(&mut SmallRng::seed_from_u64(self.display_seed));
+        }
+
         for line in lines {
             writeln!(f, "{line}")?;
         }
@@ -208,7 +367,25 @@ impl Display for GlobalScope {
     }
 }
 
-#[cfg(not(feature = "obfuscate"))]
+fn readable_alias(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut alias;
+    let mut suffix = None;
+
+    while {
+        alias = if let Some(suffix) = suffix {
+            format!("{}_{suffix}", preprocess_macro_name(name))
+        } else {
+            preprocess_macro_name(name)
+        };
+
+        taken(&alias)
+    } {
+        suffix = Some(suffix.map_or(0, |x| x + 1));
+    }
+
+    alias
+}
+
 fn preprocess_macro_name(name: &str) -> String {
     name.chars()
         .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
@@ -1,32 +1,39 @@
-use std::collections::HashMap;
+use rand::Rng;
+use rand::rngs::SmallRng;
 
+use crate::Map;
 use crate::expr::{Var, VarID};
+use crate::obfuscation::KeyEntry;
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct LocalScopeID(pub usize);
 
 pub struct LocalScope {
     next_var_id: VarID,
-    vars: HashMap<VarID, Var>,
+    vars: Map<VarID, Var>,
 
-    pub input_map: HashMap<String, VarID>,
+    pub input_map: Map<String, VarID>,
     pub output_names: Option<Vec<String>>,
 
+    rng: Option<SmallRng>,
     pub prefix_capitalization: Vec<bool>,
+    /// Readable names of the obfuscated vars
+    key: Map<VarID, KeyEntry>,
 }
 
 impl LocalScope {
-    pub fn new(prefix_capitalization: Vec<bool>) -> Self {
+    pub fn new(rng: Option<SmallRng>, prefix_capitalization: Vec<bool>) -> Self {
         Self {
             next_var_id: VarID(0),
-            vars: HashMap::new(),
-            input_map: HashMap::new(),
+            vars: Map::new(),
+            input_map: Map::new(),
             output_names: None,
+            rng,
             prefix_capitalization,
+            key: Map::new(),
         }
     }
 
-    #[cfg(not(feature = "obfuscate"))]
     pub fn new_var(
         &mut self,
         name: &str,
@@ -34,20 +41,25 @@ impl LocalScope {
         variadic: bool,
         bundle: Option<VarID>,
     ) -> VarID {
-        let mut alias;
-        let mut suffix = None;
-
-        while {
-            alias = if let Some(suffix) = suffix {
-                format!("{}{suffix}", preprocess_var_name(name))
-            } else {
-                preprocess_var_name(name)
+        if self.rng.is_some() {
+            let id = self.new_random_var(name, map_input, variadic, bundle);
+            let entry = KeyEntry {
+                name: readable_alias(name, |alias| {
+                    self.key.values().any(|existing| existing.name == alias)
+                }),
+                origin: if map_input {
+                    format!("input `{name}`")
+                } else {
+                    format!("`{name}`")
+                },
             };
+            self.key.insert(id, entry);
+            return id;
+        }
 
+        let alias = readable_alias(name, |alias| {
             self.vars.values().any(|existing| existing.name == alias)
-        } {
-            suffix = Some(suffix.map_or(0, |x| x + 1));
-        }
+        });
 
         let id = self.next_var_id;
         self.next_var_id.0 += 1;
@@ -68,8 +80,7 @@ impl LocalScope {
         id
     }
 
-    #[cfg(feature = "obfuscate")]
-    pub fn new_var(
+    fn new_random_var(
         &mut self,
         name: &str,
         map_input: bool,
@@ -78,10 +89,7 @@ impl LocalScope {
     ) -> VarID {
         const TRIES_PER_LENGTH: usize = 1024;
 
-        use rand::rngs::SmallRng;
-        use rand::{Rng, SeedableRng};
-
-        let mut rng = SmallRng::from_os_rng();
+        let rng = self.rng.as_mut().unwrap();
 
         for len in 1.. {
             for _ in 0..TRIES_PER_LENGTH {
@@ -140,9 +148,38 @@ impl LocalScope {
     pub fn get_mut_var(&mut self, id: VarID) -> &mut Var {
         self.vars.get_mut(&id).unwrap()
     }
+
+    pub fn key_entry(&self, id: VarID) -> Option<&KeyEntry> {
+        self.key.get(&id)
+    }
+
+    /// Describes what an obfuscated var holds, such as the wire it carries
+    pub fn set_origin(&mut self, id: VarID, origin: String) {
+        if let Some(entry) = self.key.get_mut(&id) {
+            entry.origin = origin;
+        }
+    }
+}
+
+fn readable_alias(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut alias;
+    let mut suffix = None;
+
+    while {
+        alias = if let Some(suffix) = suffix {
+            format!("{}{suffix}", preprocess_var_name(name))
+        } else {
+            preprocess_var_name(name)
+        };
+
+        taken(&alias)
+    } {
+        suffix = Some(suffix.map_or(0, |x| x + 1));
+    }
+
+    alias
 }
 
-#[cfg(not(feature = "obfuscate"))]
 fn preprocess_var_name(name: &str) -> String {
     name.chars()
         .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
@@ -27,6 +27,7 @@ impl Scope<'_> {
             pub fn get_scope<'a>(&'a self, id: LocalScopeID) -> Scope<'a>;
             pub fn get_macro(&self, id: MacroID) -> &Macro;
             pub fn get_alias(&self, name: &str, prefix: bool) -> String;
+            pub fn prefix_sep(&self) -> &'static str;
         }
     }
 
@@ -52,6 +53,7 @@ impl<'a> MutScope<'a> {
             pub fn get_mut_macro(&mut self, id: MacroID) -> &mut Macro;
             pub fn define(&mut self, key: String, value: String);
             pub fn get_alias(&self, name: &str, prefix: bool) -> String;
+            pub fn prefix_sep(&self) -> &'static str;
         }
     }
 
@@ -0,0 +1,158 @@
+use crate::r#macro::MacroID;
+use crate::scope::global::GlobalScope;
+
+/// Where the state of a sequential module macro is passed in and out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateLayout {
+    pub elements: Vec<StateElement>,
+}
+
+/// Register of a module or one of its submodules
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateElement {
+    /// Instances from the module down to the one holding the register, such as `["sub"]`
+    pub path: Vec<String>,
+    /// Name of the register within its module
+    pub name: String,
+    /// Indices of the register bits which are kept, in increasing order
+    pub bits: Vec<usize>,
+    /// Positions of the current value of each bit in the inputs of the macro
+    pub input_positions: Vec<usize>,
+    /// Positions of the next value of each bit in the outputs of the macro
+    pub output_positions: Vec<usize>,
+    indexed: bool,
+}
+
+impl StateLayout {
+    /// Finds the state of a module macro from the `{port}.i` inputs which yosys creates for
+    /// registers, and the `{cell}..{port}` ports which expose the state of submodules.
+    pub fn new(global_scope: &GlobalScope, macro_id: MacroID) -> Self {
+        let r#macro = global_scope.get_macro(macro_id);
+        let scope = global_scope.get_scope(r#macro.scope_id);
+        let output_names = scope.local().output_names.clone().unwrap_or_default();
+
+        let mut elements: Vec<StateElement> = Vec::new();
+        for (output_position, output) in output_names.iter().enumerate() {
+            let Some(input_position) = scope
+                .local()
+                .input_map
+                .get(&format!("{output}.i"))
+                .and_then(|var| r#macro.inputs.iter().position(|input| input == var))
+            else {
+                continue;
+            };
+
+            let mut path = output.split("..").map(str::to_string).collect::<Vec<_>>();
+            let port = path.pop().unwrap();
+            let (name, bit) = match port
+                .strip_suffix(']')
+                .and_then(|port| port.rsplit_once('['))
+            {
+                Some((name, bit)) if bit.parse::<usize>().is_ok() => {
+                    (name.to_string(), Some(bit.parse().unwrap()))
+                }
+                _ => (port, None),
+            };
+
+            let element = match elements
+                .iter_mut()
+                .find(|element| element.path == path && element.name == name)
+            {
+                Some(element) => element,
+                None => {
+                    elements.push(StateElement {
+                        path,
+                        name,
+                        bits: Vec::new(),
+                        input_positions: Vec::new(),
+                        output_positions: Vec::new(),
+                        indexed: bit.is_some(),
+                    });
+                    elements.last_mut().unwrap()
+                }
+            };
+
+            let bit = bit.unwrap_or_default();
+            let idx = element.bits.partition_point(|&existing| existing < bit);
+            element.bits.insert(idx, bit);
+            element.input_positions.insert(idx, input_position);
+            element.output_positions.insert(idx, output_position);
+        }
+
+        Self { elements }
+    }
+
+    /// Pairs of output and input positions which carry the same state bit
+    pub fn feedback(&self) -> impl Iterator<Item = (usize, usize)> {
+        self.elements.iter().flat_map(|element| {
+            element
+                .output_positions
+                .iter()
+                .copied()
+                .zip(element.input_positions.iter().copied())
+        })
+    }
+
+    pub fn is_state_input(&self, position: usize) -> bool {
+        self.elements
+            .iter()
+            .any(|element| element.input_positions.contains(&position))
+    }
+
+    pub fn is_state_output(&self, position: usize) -> bool {
+        self.elements
+            .iter()
+            .any(|element| element.output_positions.contains(&position))
+    }
+
+    /// Finds a register by its hierarchical name, such as `sub..sub_cnt`
+    pub fn element(&self, name: &str) -> Option<&StateElement> {
+        self.elements
+            .iter()
+            .find(|element| element.hierarchical_name() == name)
+    }
+}
+
+impl StateElement {
+    pub fn width(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Name including the instance path, such as `sub..sub_cnt`
+    pub fn hierarchical_name(&self) -> String {
+        self.path
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(self.name.as_str()))
+            .collect::<Vec<_>>()
+            .join("..")
+    }
+
+    /// Name of the output port for the bit at `idx` in [`bits`](StateElement::bits)
+    pub fn output_port(&self, idx: usize) -> String {
+        if self.indexed {
+            format!("{}[{}]", self.hierarchical_name(), self.bits[idx])
+        } else {
+            self.hierarchical_name()
+        }
+    }
+
+    /// Name of the input port for the bit at `idx` in [`bits`](StateElement::bits)
+    pub fn input_port(&self, idx: usize) -> String {
+        format!("{}.i", self.output_port(idx))
+    }
+
+    /// Object-like macro holding the bit at `idx` in [`bits`](StateElement::bits) outside of macro
+    /// calls, such as `STATE_sub__sub_cnt_2`
+    pub fn define_name(&self, idx: usize) -> String {
+        format!("STATE_{}", port_identifier(&self.output_port(idx)))
+    }
+}
+
+/// Turns a port name such as `sub..sub_cnt[2]` into an identifier such as `sub__sub_cnt_2`
+pub fn port_identifier(port: &str) -> String {
+    port.chars()
+        .filter(|&c| c != ']')
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
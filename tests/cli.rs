@@ -0,0 +1,31 @@
+use std::process::Command;
+
+#[test]
+fn test_cli_errors() {
+    for args in [
+        vec!["tests/run.sv", "--chparam", "WIDTH=8"],
+        vec!["tests/run.sv", "--eval-branching", "1"],
+        vec!["tests/run.sv", "--cont", "cont", "--max-cycles", "0"],
+        vec!["--config", "tests/missing.toml"],
+        vec!["deobfuscate", "--key", "tests/missing.json"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_vlogpp"))
+            .args(&args)
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .starts_with("error: ")
+        );
+    }
+
+    // Invalid arguments are reported by the parser
+    let status = Command::new(env!("CARGO_BIN_EXE_vlogpp"))
+        .args(["tests/run.sv", "--config", "vlogpp.toml"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(2));
+}
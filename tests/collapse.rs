@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::iter::once;
+use std::process::Command;
+
+use vlogpp::expr::Expr;
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_collapsed_submods() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/submod.sv", false, &[]).unwrap();
+    let registry = Registry::default()
+        .add_netlist(netlist.clone())
+        .collapse_modules(9);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+
+    // Only the 4-bit adders are small enough to collapse
+    assert_eq!(assert_collapsed(&mut global_scope, &netlist, 9), 1);
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    let top_macro = global_scope.get_macro(top);
+
+    for (a, b, c) in [(106_usize, 22, 1), (211, 165, 0)] {
+        let a_bits = format!("{:08b}", a)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, c)| (format!("a[{idx}]"), c))
+            .collect::<Vec<_>>();
+        let b_bits = format!("{:08b}", b)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, c)| (format!("b[{idx}]"), c))
+            .collect::<Vec<_>>();
+        let c_bit = if c == 1 {
+            ("c".to_string(), '1')
+        } else {
+            ("c".to_string(), '0')
+        };
+
+        let mut inputs = a_bits
+            .iter()
+            .chain(b_bits.iter())
+            .chain(once(&c_bit))
+            .map(|(name, value)| {
+                (
+                    top_macro.input_position(name, &global_scope).unwrap(),
+                    *value,
+                )
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|(idx, _)| *idx);
+
+        let output_map = global_scope
+            .get_scope(top_macro.scope_id)
+            .local()
+            .output_names
+            .clone()
+            .unwrap();
+        let mut output_bits = format!("{:09b}", a + b + c)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, char)| {
+                (
+                    output_map
+                        .iter()
+                        .position(|out| out == &format!("out[{idx}]"))
+                        .unwrap(),
+                    char,
+                )
+            })
+            .collect::<Vec<_>>();
+        output_bits.sort_by_key(|(idx, _)| *idx);
+
+        let text = format!(
+            "{macro_text}\n{}({})",
+            &top_macro.name,
+            inputs
+                .iter()
+                .map(|(_, var)| var.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut file = File::create("test_collapse.h").unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let status = Command::new("gcc")
+            .arg("-E")
+            .arg("-P")
+            .arg("test_collapse.h")
+            .arg("-o")
+            .arg("test_collapse_out.h")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let actual = fs::read_to_string("test_collapse_out.h").unwrap();
+        assert_eq!(
+            actual.replace(" ", ""),
+            format!(
+                "{}\n",
+                output_bits
+                    .iter()
+                    .map(|(_, out)| out.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        );
+    }
+}
+
+#[test]
+fn test_evaluate_missing_input() {
+    let netlist: Netlist =
+        serde_json::from_str(&fs::read_to_string("tests/gates.json").unwrap()).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let inputs = [("a", 1), ("b", 0), ("A", 1), ("B", 0)]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect::<HashMap<_, _>>();
+    assert!(registry.evaluate("full_adder", &inputs).is_none());
+    assert!(registry.evaluate("$_MUX_", &inputs).is_none());
+    assert!(registry.evaluate("$_AND_", &inputs).is_some());
+}
+
+#[test]
+fn test_collapsed_gates() {
+    let netlist: Netlist =
+        serde_json::from_str(&fs::read_to_string("tests/gates.json").unwrap()).unwrap();
+    let registry = Registry::default()
+        .add_netlist(netlist.clone())
+        .collapse_modules(3);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    Registry::top_modules(&mut global_scope);
+    assert_eq!(
+        assert_collapsed(&mut global_scope, &netlist, 3),
+        netlist.modules.len()
+    );
+}
+
+/// Checks that every module with up to `max_inputs` inputs became a single paste over the rows of
+/// its table, without calls to other modules. Returns the number of collapsed modules.
+fn assert_collapsed(global_scope: &mut GlobalScope, netlist: &Netlist, max_inputs: usize) -> usize {
+    let macro_text = global_scope.emit();
+
+    let mut collapsed = 0;
+    for (name, module) in &netlist.modules {
+        let num_inputs = module.input_ports().count();
+        if num_inputs > max_inputs {
+            continue;
+        }
+
+        let id = Registry::module(global_scope, name).unwrap();
+        let paste = Registry::paste_macro(global_scope, num_inputs + 1, true);
+        let Expr::Call { r#macro, args } = &global_scope.get_macro(id).expr else {
+            panic!("Module `{name}` isn't a table lookup");
+        };
+        assert_eq!(**r#macro, Expr::Macro(paste));
+        let [Expr::Text(prefix), vars @ ..] = args.as_slice() else {
+            panic!("Module `{name}` isn't a table lookup");
+        };
+        assert_eq!(vars.len(), num_inputs);
+        assert!(vars.iter().all(|var| matches!(var, Expr::Var(_))));
+
+        let rows = macro_text
+            .lines()
+            .filter(|line| line.starts_with(&format!("#define {prefix}")))
+            .count();
+        assert_eq!(rows, 1 << num_inputs);
+        collapsed += 1;
+    }
+
+    collapsed
+}
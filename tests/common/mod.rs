@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use vlogpp::harness::preprocess;
+use vlogpp::r#macro::MacroID;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+/// Every combination of binary values for the named inputs
+pub fn all_inputs(names: &[String]) -> Vec<HashMap<String, usize>> {
+    (0..1_usize << names.len())
+        .map(|idx| {
+            names
+                .iter()
+                .enumerate()
+                .map(|(bit, name)| (name.clone(), (idx >> bit) & 1))
+                .collect()
+        })
+        .collect()
+}
+
+/// Calls the combinational macros `tops` with every combination of their inputs in one
+/// preprocessor run, and checks the outputs against the simulation of `original`
+pub fn assert_matches_simulation(
+    global_scope: &GlobalScope,
+    tops: &[MacroID],
+    original: &Registry,
+) {
+    let mut calls = String::new();
+    let mut expected = String::new();
+    for &top in tops {
+        let r#macro = global_scope.get_macro(top);
+        let doc_name = r#macro.doc_name.clone().unwrap();
+        let input_names = r#macro
+            .input_names(global_scope)
+            .into_iter()
+            .map(Option::unwrap)
+            .collect::<Vec<_>>();
+        let output_names = global_scope
+            .get_scope(r#macro.scope_id)
+            .local()
+            .output_names
+            .clone()
+            .unwrap();
+
+        for inputs in all_inputs(&input_names) {
+            let args = input_names
+                .iter()
+                .map(|name| inputs.get(name).unwrap().to_string())
+                .collect::<Vec<_>>();
+            calls.push_str(&format!("{}({})\n", r#macro.name, args.join(", ")));
+
+            let outputs = original.evaluate(&doc_name, &inputs).unwrap();
+            let values = output_names
+                .iter()
+                .map(|name| outputs.get(name).unwrap().to_string())
+                .collect::<Vec<_>>();
+            expected.push_str(&format!("{}\n", values.join(",")));
+        }
+    }
+
+    let output = preprocess(&format!("{}\n{calls}", global_scope.emit())).unwrap();
+    assert_eq!(output.replace(" ", ""), expected);
+}
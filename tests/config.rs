@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use vlogpp::config::Config;
+use vlogpp::halt::HaltCondition;
+use vlogpp::obfuscation::ObfuscationConfig;
+
+#[test]
+fn test_config() {
+    let config = Config::from_file("tests/config.toml").unwrap();
+
+    assert_eq!(config.sources, [PathBuf::from("tests/run.sv")]);
+    assert_eq!(config.output.path, Some(PathBuf::from("tests/run.h")));
+    assert_eq!(config.tops, ["run"]);
+    assert!(!config.lint.enabled);
+    assert_eq!(config.variadic_threshold, 1);
+    assert_eq!(config.eval_branching, 4);
+
+    assert_eq!(config.repeat.len(), 1);
+    assert_eq!(
+        config.repeat[0].halt,
+        HaltCondition::Low("cont".to_string())
+    );
+    assert_eq!(config.repeat[0].max_cycles, Some(100));
+
+    assert_eq!(config.luts.len(), 1);
+    assert!(config.luts[0].to_lut().is_ok());
+}
+
+#[test]
+fn test_config_errors() {
+    let mut config = Config::from_file("tests/config.toml").unwrap();
+    config.luts[0].table.pop();
+    assert!(config.luts[0].to_lut().is_err());
+
+    let mut config = Config::from_file("tests/config.toml").unwrap();
+    config.repeat[0].max_cycles = Some(0);
+    assert!(config.build().is_err());
+
+    let mut config = Config::from_file("tests/config.toml").unwrap();
+    config.obfuscation = ObfuscationConfig::readable().scramble_wires(true);
+    assert!(config.build().is_err());
+
+    assert!(toml::from_str::<Config>("sources = []\nunknown = 1").is_err());
+    assert!(Config::from_file("tests/missing.toml").is_err());
+}
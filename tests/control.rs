@@ -0,0 +1,126 @@
+use std::fs;
+use std::process::Command;
+
+use vlogpp::halt::HaltCondition;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_control_flow() {
+    let mut global_scope = GlobalScope::new(Registry::default(), ObfuscationConfig::default());
+    let macros = [
+        Registry::while_macro(&mut global_scope),
+        Registry::for_each_macro(&mut global_scope),
+        Registry::repeat_n_macro(&mut global_scope, 5),
+        Registry::bool_macro(&mut global_scope),
+        Registry::not_macro(&mut global_scope),
+        Registry::compl_macro(&mut global_scope),
+        Registry::rest_macro(&mut global_scope),
+        Registry::pick_macro(&mut global_scope, 1),
+        Registry::eval_macro(&mut global_scope, 3),
+    ]
+    .map(|id| global_scope.get_macro(id).name.clone());
+    let [
+        while_,
+        for_each,
+        repeat,
+        bool_,
+        not,
+        compl,
+        rest,
+        pick,
+        eval,
+    ] = &macros;
+
+    // Hand-written macros which count down and collect the visited values
+    let cases = [
+        (
+            format!("{eval}({while_}(TEST_PRED, TEST_OP, 3, ))"),
+            "0, 3 2 1",
+        ),
+        (
+            format!("{eval}({for_each}(TEST_PAIR, (a, b)(c, d)(e, f)))"),
+            "[a-b] [c-d] [e-f]",
+        ),
+        (format!("{eval}({for_each}(TEST_PAIR, ))"), ""),
+        (format!("{repeat}(3, TEST_INDEXED, z)"), "z0 z1 z2"),
+        (format!("{repeat}(0, TEST_INDEXED, z)"), ""),
+        (
+            format!("{bool_}(5) {bool_}(0) {not}(abc) {not}(0) {compl}(0) {compl}(1)"),
+            "1 0 0 1 1 0",
+        ),
+        (format!("{rest}(a, b, c) {pick}(a, b, c)"), "b, c b"),
+    ];
+
+    let text = format!(
+        "{}\n\
+         #define TEST_CAT(a, b) TEST_CAT_(a, b)\n\
+         #define TEST_CAT_(a, b) a##b\n\
+         #define TEST_DEC_3 2\n\
+         #define TEST_DEC_2 1\n\
+         #define TEST_DEC_1 0\n\
+         #define TEST_PRED(x, acc) x\n\
+         #define TEST_OP(x, acc) TEST_CAT(TEST_DEC_, x), acc x\n\
+         #define TEST_PAIR(x, y) [x-y]\n\
+         #define TEST_INDEXED(idx, x) TEST_CAT(x, idx)\n\
+         {}\n",
+        global_scope.emit(),
+        cases
+            .iter()
+            .map(|(call, _)| call.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    fs::write("test_control.h", text).unwrap();
+
+    let output = Command::new("gcc")
+        .arg("-E")
+        .arg("-P")
+        .arg("test_control.h")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let actual = String::from_utf8(output.stdout).unwrap();
+    let actual = actual
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>();
+    let expected = cases
+        .iter()
+        .map(|(_, expected)| expected.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(actual[actual.len() - expected.len()..], expected);
+}
+
+#[test]
+fn test_empty_halt_condition() {
+    let mut global_scope = GlobalScope::new(Registry::default(), ObfuscationConfig::default());
+    let and = Registry::module(&mut global_scope, "$_AND_").unwrap();
+
+    for halt in [
+        HaltCondition::Any(Vec::new()),
+        HaltCondition::Equals(Vec::new()),
+    ] {
+        assert!(Registry::repeat_macro(&mut global_scope, and, &halt).is_err());
+        assert!(Registry::trace_macro(&mut global_scope, and, &halt, None).is_err());
+    }
+}
+
+#[test]
+fn test_halt_condition_x() {
+    let mut global_scope = GlobalScope::new(
+        Registry::default().propagate_x(),
+        ObfuscationConfig::default(),
+    );
+    let and = Registry::module(&mut global_scope, "$_AND_").unwrap();
+
+    for halt in [
+        HaltCondition::Low("Y".to_string()),
+        HaltCondition::Any(vec!["Y".to_string()]),
+    ] {
+        assert!(Registry::repeat_macro(&mut global_scope, and, &halt).is_err());
+        assert!(Registry::trace_macro(&mut global_scope, and, &halt, None).is_err());
+    }
+}
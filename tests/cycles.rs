@@ -0,0 +1,123 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::Command;
+
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_cycles() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/submod_state.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    let cycle_counts = [1_usize, 2, 7, 20];
+    let cycle_macros = cycle_counts
+        .iter()
+        .map(|&cycles| Registry::cycle_macro(&mut global_scope, top, cycles))
+        .collect::<Vec<_>>();
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    let top_macro = global_scope.get_macro(top);
+    let output_map = global_scope
+        .get_scope(top_macro.scope_id)
+        .local()
+        .output_names
+        .clone()
+        .unwrap();
+
+    for (&cycles, &cycle_macro) in cycle_counts.iter().zip(&cycle_macros) {
+        let (sub_in, main_in) = (13_usize, 201_usize);
+        let sub_in_bits = format!("{:04b}", sub_in)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, c)| (format!("sub..sub_cnt[{idx}].i"), c))
+            .collect::<Vec<_>>();
+        let main_in_bits = format!("{:08b}", main_in)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, c)| (format!("cnt[{idx}].i"), c))
+            .collect::<Vec<_>>();
+
+        let mut inputs = sub_in_bits
+            .iter()
+            .chain(main_in_bits.iter())
+            .map(|(name, value)| {
+                (
+                    top_macro.input_position(name, &global_scope).unwrap(),
+                    *value,
+                )
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|(idx, _)| *idx);
+
+        let main_out = (0..cycles).fold(main_in, |acc, cycle| (acc + (sub_in + cycle) % 16) % 256);
+        let mut output_bits = format!("{:08b}", main_out)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, char)| (format!("cnt[{idx}]"), char))
+            .chain(
+                format!("{:04b}", (sub_in + cycles) % 16)
+                    .chars()
+                    .rev()
+                    .enumerate()
+                    .map(|(idx, char)| (format!("sub..sub_cnt[{idx}]"), char)),
+            )
+            .map(|(name, char)| {
+                (
+                    output_map.iter().position(|out| out == &name).unwrap(),
+                    char,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        output_bits.sort_by_key(|(idx, _)| *idx);
+
+        let text = format!(
+            "{macro_text}\n{}({})",
+            &global_scope.get_macro(cycle_macro).name,
+            inputs
+                .iter()
+                .map(|(_, var)| var.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut file = File::create("test_cycles.h").unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let status = Command::new("gcc")
+            .arg("-E")
+            .arg("-P")
+            .arg("test_cycles.h")
+            .arg("-o")
+            .arg("test_cycles_out.h")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let actual = fs::read_to_string("test_cycles_out.h").unwrap();
+        assert_eq!(
+            actual.replace(" ", ""),
+            format!(
+                "{}\n",
+                output_bits
+                    .iter()
+                    .map(|(_, out)| out.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        );
+    }
+}
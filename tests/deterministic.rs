@@ -0,0 +1,39 @@
+use std::fs;
+
+use vlogpp::mapping::MappingGoal;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+fn emit(obfuscation: ObfuscationConfig, map_luts: bool) -> String {
+    let netlist: Netlist =
+        serde_json::from_str(&fs::read_to_string("tests/gates.json").unwrap()).unwrap();
+    let mut registry = Registry::default().add_netlist(netlist);
+    if map_luts {
+        registry = registry.map_luts(3, MappingGoal::Lookups);
+    }
+
+    let mut global_scope = GlobalScope::new(registry, obfuscation);
+    assert_eq!(Registry::top_modules(&mut global_scope).len(), 3);
+    global_scope.variadicify_macros(1);
+    global_scope.emit()
+}
+
+#[test]
+fn test_deterministic_output() {
+    for map_luts in [false, true] {
+        let first = emit(ObfuscationConfig::readable(), map_luts);
+        for _ in 0..8 {
+            assert_eq!(emit(ObfuscationConfig::readable(), map_luts), first);
+        }
+
+        let first = emit(ObfuscationConfig::obfuscated().seed(3), map_luts);
+        for _ in 0..8 {
+            assert_eq!(
+                emit(ObfuscationConfig::obfuscated().seed(3), map_luts),
+                first
+            );
+        }
+    }
+}
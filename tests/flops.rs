@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::registry::Registry;
+
+const REGISTERS: [&str; 3] = ["cnt", "acc", "last"];
+
+fn bits(values: [usize; 3]) -> HashMap<String, usize> {
+    REGISTERS
+        .iter()
+        .zip(values)
+        .flat_map(|(name, value)| {
+            (0..4).map(move |idx| (format!("{name}[{idx}]"), (value >> idx) & 1))
+        })
+        .collect()
+}
+
+#[test]
+fn test_flip_flop_types() {
+    lint_directory("tests").unwrap();
+
+    // An asynchronous reset with an enable, a synchronous reset over an enable, and a synchronous
+    // reset which only applies while enabled
+    let netlist = Netlist::new("tests/flops.sv", false, &[]).unwrap();
+    let module = netlist.modules.get("flops").unwrap();
+    let reset_values = module
+        .reset_values
+        .iter()
+        .map(|(name, &value)| (name.strip_suffix(".i").unwrap().to_string(), value as usize))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(reset_values, bits([5, 0, 9]));
+
+    let registry = Registry::default().add_netlist(netlist);
+    let mut state = [5, 0, 9];
+    for (rst, en) in [(0, 1), (0, 1), (0, 0), (1, 0), (0, 1), (1, 1), (0, 1)] {
+        let mut inputs = bits(state)
+            .into_iter()
+            .map(|(name, value)| (format!("{name}.i"), value))
+            .collect::<HashMap<_, _>>();
+        inputs.insert("rst".to_string(), rst);
+        inputs.insert("en".to_string(), en);
+        let outputs = registry.evaluate("flops", &inputs).unwrap();
+
+        let [cnt, acc, last] = state;
+        state = [
+            if rst == 1 {
+                5
+            } else if en == 1 {
+                (cnt + 1) % 16
+            } else {
+                cnt
+            },
+            if rst == 1 {
+                0
+            } else if en == 1 {
+                acc ^ cnt
+            } else {
+                acc
+            },
+            match (en, rst) {
+                (1, 1) => 9,
+                (1, _) => cnt,
+                _ => last,
+            },
+        ];
+        assert_eq!(outputs, bits(state));
+    }
+}
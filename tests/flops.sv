@@ -0,0 +1,46 @@
+`default_nettype none
+
+module flops (
+    input var logic clk,
+    input var logic rst,
+    input var logic en,
+    output var logic [3:0] cnt,
+    output var logic [3:0] acc,
+    output var logic [3:0] last
+);
+
+    /* svlint off eventlist_or */
+    always_ff @(posedge clk or posedge rst) begin
+        if (rst) begin
+            cnt <= 4'd5;
+        end else if (en) begin
+            cnt <= cnt + 1;
+        end else begin
+            cnt <= cnt;
+        end
+    end
+    /* svlint on eventlist_or */
+
+    always_ff @(posedge clk) begin
+        if (rst) begin
+            acc <= 4'd0;
+        end else if (en) begin
+            acc <= acc ^ cnt;
+        end else begin
+            acc <= acc;
+        end
+    end
+
+    always_ff @(posedge clk) begin
+        if (en) begin
+            if (rst) begin
+                last <= 4'd9;
+            end else begin
+                last <= cnt;
+            end
+        end else begin
+            last <= last;
+        end
+    end
+
+endmodule
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use vlogpp::harness::Harness;
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_harness() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/submod_state.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    global_scope.variadicify_macros(2);
+
+    let bits = |name: &str, value: usize, width: usize| {
+        (0..width)
+            .map(|idx| (format!("{name}[{idx}]"), ((value >> idx) & 1).to_string()))
+            .collect::<Vec<_>>()
+    };
+    let value = |outputs: &HashMap<String, String>, name: &str, width: usize| {
+        (0..width)
+            .map(|idx| (outputs[&format!("{name}[{idx}]")].parse::<usize>().unwrap()) << idx)
+            .sum::<usize>()
+    };
+
+    let (mut sub_cnt, mut cnt) = (13_usize, 201_usize);
+    let state = bits("sub..sub_cnt", sub_cnt, 4)
+        .into_iter()
+        .chain(bits("cnt", cnt, 8))
+        .map(|(name, value)| (format!("{name}.i"), value))
+        .collect::<HashMap<_, _>>();
+
+    let harness = Harness::new(&global_scope, top).unwrap();
+    let trace = harness.run(&state, &vec![HashMap::new(); 20]).unwrap();
+    assert_eq!(trace.len(), 20);
+
+    for outputs in trace {
+        cnt = (cnt + sub_cnt) % 256;
+        sub_cnt = (sub_cnt + 1) % 16;
+
+        assert_eq!(value(&outputs, "cnt", 8), cnt);
+        assert_eq!(value(&outputs, "sub..sub_cnt", 4), sub_cnt);
+    }
+
+    assert!(harness.step(&HashMap::new()).is_err());
+}
+
+#[test]
+fn test_harness_unnamed_inputs() {
+    let mut global_scope = GlobalScope::new(Registry::default(), ObfuscationConfig::default());
+    let while_ = Registry::while_macro(&mut global_scope);
+    assert!(Harness::new(&global_scope, while_).is_err());
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::process::Command;
+
+use vlogpp::halt::HaltCondition;
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_iterate() {
+    // Without a halt condition all cycles are run, and the count wraps around
+    check_iterate("iterate", 1234, None, 3, 5, true);
+}
+
+#[test]
+fn test_iterate_halt() {
+    let cont = HaltCondition::Low("cont".to_string());
+    check_iterate("iterate_halt", 1000, Some(&cont), 3, 10, false);
+}
+
+#[test]
+fn test_iterate_checkpoint() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/run.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let first = Registry::iteration_header(
+        &mut global_scope,
+        "run",
+        "test_checkpoint_first.h",
+        600,
+        None,
+    )
+    .unwrap();
+    let second = Registry::iteration_header(
+        &mut global_scope,
+        "run",
+        "test_checkpoint_second.h",
+        634,
+        None,
+    )
+    .unwrap();
+    let checkpoint = Registry::checkpoint_header(&mut global_scope, "run").unwrap();
+    fs::write("test_checkpoint_first.h", first).unwrap();
+    fs::write("test_checkpoint_second.h", second).unwrap();
+    fs::write("test_checkpoint.h", checkpoint).unwrap();
+
+    // Running 600 and then 634 cycles from the printed checkpoint matches a single run
+    fs::write(
+        "test_checkpoint_first.c",
+        "#define STATE_cnt_0 1\n#define STATE_cnt_1 1\n#define STATE_cnt_2 0\n#define STATE_cnt_3 0\n\
+         #include \"test_checkpoint_first.h\"\n#include \"test_checkpoint.h\"\n",
+    )
+    .unwrap();
+    let state = preprocess("test_checkpoint_first.c");
+    assert_eq!(
+        state.lines().map(str::trim).collect::<Vec<_>>(),
+        [
+            "# define STATE_cnt_0 1",
+            "# define STATE_cnt_1 1",
+            "# define STATE_cnt_2 0",
+            "# define STATE_cnt_3 1"
+        ]
+    );
+    fs::write("test_checkpoint_state.h", state).unwrap();
+
+    fs::write(
+        "test_checkpoint_second.c",
+        "#include \"test_checkpoint_state.h\"\n#include \"test_checkpoint_second.h\"\n\
+         STATE_cnt_3 STATE_cnt_2 STATE_cnt_1 STATE_cnt_0 OUTPUT_cont\n",
+    )
+    .unwrap();
+    assert_eq!(preprocess("test_checkpoint_second.c").trim(), "0 1 0 1 1");
+}
+
+#[test]
+fn test_iterate_unknown_output() {
+    let netlist = Netlist::new("tests/run.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let halt = HaltCondition::High("done".to_string());
+    assert!(
+        Registry::iteration_header(&mut global_scope, "run", "run.h", 10, Some(&halt)).is_err()
+    );
+}
+
+fn check_iterate(
+    name: &str,
+    cycles: usize,
+    halt: Option<&HaltCondition>,
+    start: usize,
+    cnt: usize,
+    cont: bool,
+) {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/run.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let header_name = format!("test_{name}.h");
+    let header =
+        Registry::iteration_header(&mut global_scope, "run", &header_name, cycles, halt).unwrap();
+    fs::write(&header_name, header).unwrap();
+
+    // The module has no reset values, so the starting state is defined before the header
+    let driver = (0..4)
+        .map(|idx| format!("#define STATE_cnt_{idx} {}\n", (start >> idx) & 1))
+        .chain(std::iter::once(format!("#include \"{header_name}\"\n")))
+        .chain((0..4).rev().map(|idx| format!("STATE_cnt_{idx} ")))
+        .chain(std::iter::once("OUTPUT_cont\n".to_string()))
+        .collect::<String>();
+    let path = format!("test_{name}.c");
+    fs::write(&path, driver).unwrap();
+
+    assert_eq!(
+        preprocess(&path).trim(),
+        format!("{cnt:04b}")
+            .chars()
+            .map(|bit| bit.to_string())
+            .chain(std::iter::once((cont as u8).to_string()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+}
+
+fn preprocess(path: &str) -> String {
+    let output = Command::new("gcc")
+        .arg("-E")
+        .arg("-P")
+        .arg(path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout).unwrap()
+}
@@ -0,0 +1,216 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::Command;
+
+use vlogpp::lut::Lut;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_decomposed_lut() {
+    let input_names = ["a", "b", "c", "d", "e", "f"];
+    let function = |bits: &[bool]| {
+        if bits[0] && bits[1] {
+            bits[2] ^ bits[3] ^ bits[4] ^ bits[5]
+        } else {
+            !bits[0] && bits[2] && bits[3]
+        }
+    };
+    let outputs = (0..64_usize)
+        .map(|idx| {
+            function(
+                &(0..6)
+                    .map(|bit| (idx >> (5 - bit)) & 1 == 1)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let registry = Registry::default()
+        .limit_lut_inputs(3)
+        .register_lut(Lut::new("wide", &input_names, &["y"], &outputs));
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let wide = Registry::module(&mut global_scope, "wide").unwrap();
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    assert!(macro_text.lines().count() < outputs.len());
+
+    let wide_macro = global_scope.get_macro(wide);
+    let calls = (0..64_usize)
+        .map(|idx| {
+            let mut inputs = input_names
+                .iter()
+                .enumerate()
+                .map(|(bit, name)| {
+                    (
+                        wide_macro.input_position(name, &global_scope).unwrap(),
+                        (idx >> (5 - bit)) & 1,
+                    )
+                })
+                .collect::<Vec<_>>();
+            inputs.sort_by_key(|(idx, _)| *idx);
+
+            format!(
+                "{}({})",
+                &wide_macro.name,
+                inputs
+                    .iter()
+                    .map(|(_, value)| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut file = File::create("test_lut.h").unwrap();
+    file.write_all(format!("{macro_text}\n{calls}").as_bytes())
+        .unwrap();
+
+    let status = Command::new("gcc")
+        .arg("-E")
+        .arg("-P")
+        .arg("test_lut.h")
+        .arg("-o")
+        .arg("test_lut_out.h")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let actual = fs::read_to_string("test_lut_out.h").unwrap();
+    assert_eq!(
+        actual.replace(" ", ""),
+        outputs
+            .iter()
+            .map(|&output| if output { "1\n" } else { "0\n" })
+            .collect::<String>()
+    );
+}
+
+#[test]
+fn test_decimal_adder() {
+    let lut = Lut::digit_adder(10);
+    let name = lut.name.clone();
+    let registry = Registry::default().limit_lut_inputs(2).register_lut(lut);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let adder = Registry::module(&mut global_scope, &name).unwrap();
+    let macro_text = global_scope.emit();
+    let adder_macro = global_scope.get_macro(adder);
+
+    let mut calls = Vec::new();
+    let mut expected = String::new();
+    for (a, b, c) in (0..10_usize).flat_map(|a| (0..10).flat_map(move |b| [(a, b, 0), (a, b, 1)])) {
+        let mut inputs = [("A", a), ("B", b), ("C", c)]
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    adder_macro.input_position(name, &global_scope).unwrap(),
+                    value,
+                )
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|(idx, _)| *idx);
+
+        calls.push(format!(
+            "{}({})",
+            &adder_macro.name,
+            inputs
+                .iter()
+                .map(|(_, value)| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        expected.push_str(&format!("{},{}\n", (a + b + c) % 10, (a + b + c) / 10));
+    }
+
+    let mut file = File::create("test_decimal.h").unwrap();
+    file.write_all(format!("{macro_text}\n{}", calls.join("\n")).as_bytes())
+        .unwrap();
+
+    let status = Command::new("gcc")
+        .arg("-E")
+        .arg("-P")
+        .arg("test_decimal.h")
+        .arg("-o")
+        .arg("test_decimal_out.h")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let actual = fs::read_to_string("test_decimal_out.h").unwrap();
+    assert_eq!(actual.replace(" ", ""), expected);
+}
+
+#[test]
+fn test_x_propagation() {
+    let registry = Registry::default().propagate_x();
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+
+    let cases = [
+        ("$_AND_", vec![("A", "0"), ("B", "X")], "0"),
+        ("$_AND_", vec![("A", "1"), ("B", "X")], "X"),
+        ("$_OR_", vec![("A", "1"), ("B", "X")], "1"),
+        ("$_XOR_", vec![("A", "1"), ("B", "X")], "X"),
+        ("$_NOT_", vec![("A", "X")], "X"),
+        ("$_MUX_", vec![("S", "X"), ("A", "1"), ("B", "1")], "1"),
+        ("$_MUX_", vec![("S", "X"), ("A", "0"), ("B", "1")], "X"),
+        ("$_MUX_", vec![("S", "0"), ("A", "0"), ("B", "X")], "0"),
+    ];
+
+    let mut calls = Vec::new();
+    for (name, inputs, _) in &cases {
+        let gate = Registry::module(&mut global_scope, name).unwrap();
+        let gate_macro = global_scope.get_macro(gate);
+
+        let mut inputs = inputs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    gate_macro.input_position(name, &global_scope).unwrap(),
+                    *value,
+                )
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|(idx, _)| *idx);
+
+        calls.push(format!(
+            "{}({})",
+            &gate_macro.name,
+            inputs
+                .iter()
+                .map(|(_, value)| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    let mut file = File::create("test_x.h").unwrap();
+    file.write_all(format!("{}\n{}", global_scope.emit(), calls.join("\n")).as_bytes())
+        .unwrap();
+
+    let status = Command::new("gcc")
+        .arg("-E")
+        .arg("-P")
+        .arg("test_x.h")
+        .arg("-o")
+        .arg("test_x_out.h")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let actual = fs::read_to_string("test_x_out.h").unwrap();
+    assert_eq!(
+        actual.replace(" ", ""),
+        cases
+            .iter()
+            .map(|(_, _, output)| format!("{output}\n"))
+            .collect::<String>()
+    );
+}
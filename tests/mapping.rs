@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fs;
+
+use vlogpp::Map;
+use vlogpp::lut::Lut;
+use vlogpp::mapping::{MappingGoal, map_module};
+use vlogpp::netlist::{Netlist, Wire};
+use vlogpp::registry::Registry;
+
+#[test]
+fn test_mapped_gates() {
+    let netlist: Netlist =
+        serde_json::from_str(&fs::read_to_string("tests/gates.json").unwrap()).unwrap();
+    let luts = [Lut::not(), Lut::or(), Lut::and(), Lut::xor(), Lut::mux()]
+        .into_iter()
+        .map(|lut| (lut.name.clone(), lut))
+        .collect::<Map<_, _>>();
+
+    let module = netlist.modules.get("full_adder").unwrap();
+    let (mapped, new_luts) = map_module(module, &luts, 4, MappingGoal::Lookups);
+    assert!(mapped.cells.len() < module.cells.len());
+    assert!(
+        mapped
+            .cells
+            .values()
+            .any(|cell| cell.kind.starts_with("$_LUT"))
+    );
+    assert!(mapped.cells.values().all(|cell| {
+        let lut = luts
+            .get(&cell.kind)
+            .or_else(|| new_luts.iter().find(|lut| lut.name == cell.kind))
+            .unwrap();
+        lut.input_names.len() <= 4
+    }));
+
+    let original = Registry::default().add_netlist(netlist.clone());
+    let mapped = Registry::default()
+        .add_netlist(netlist.clone())
+        .map_luts(4, MappingGoal::Lookups);
+    for (name, module) in &netlist.modules {
+        let names = module
+            .input_ports()
+            .map(|(port, _)| port.clone())
+            .collect::<Vec<_>>();
+        for idx in 0..1_usize << names.len() {
+            let inputs = names
+                .iter()
+                .enumerate()
+                .map(|(bit, name)| (name.clone(), (idx >> bit) & 1))
+                .collect::<HashMap<_, _>>();
+            assert_eq!(
+                mapped.evaluate(name, &inputs),
+                original.evaluate(name, &inputs)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_multi_output_boundary() {
+    // A full adder lut feeding gates, which can only be merged up to its outputs
+    let netlist: Netlist = serde_json::from_str(
+        r#"{
+            "creator": "hand-written",
+            "modules": {
+                "top": {
+                    "attributes": { "top": "00000000000000000000000000000001" },
+                    "ports": {
+                        "a": { "direction": "input", "bits": [ 2 ] },
+                        "b": { "direction": "input", "bits": [ 3 ] },
+                        "c": { "direction": "input", "bits": [ 4 ] },
+                        "y": { "direction": "output", "bits": [ 8 ] }
+                    },
+                    "cells": {
+                        "add": {
+                            "type": "full_adder",
+                            "port_directions": {
+                                "A": "input", "B": "input", "C": "input",
+                                "S": "output", "CO": "output"
+                            },
+                            "connections": {
+                                "A": [ 2 ], "B": [ 3 ], "C": [ 4 ], "S": [ 5 ], "CO": [ 6 ]
+                            }
+                        },
+                        "xor": {
+                            "type": "$_XOR_",
+                            "port_directions": { "A": "input", "B": "input", "Y": "output" },
+                            "connections": { "A": [ 5 ], "B": [ 6 ], "Y": [ 7 ] }
+                        },
+                        "not": {
+                            "type": "$_NOT_",
+                            "port_directions": { "A": "input", "Y": "output" },
+                            "connections": { "A": [ 7 ], "Y": [ 8 ] }
+                        }
+                    }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let outputs = (0..8_usize)
+        .flat_map(|idx| [idx.count_ones() % 2 == 1, idx.count_ones() >= 2])
+        .collect::<Vec<_>>();
+    let registry = || {
+        Registry::default().register_lut(Lut::new(
+            "full_adder",
+            &["A", "B", "C"],
+            &["S", "CO"],
+            &outputs,
+        ))
+    };
+    let original = registry().add_netlist(netlist.clone());
+    let mapped = registry()
+        .add_netlist(netlist)
+        .map_luts(4, MappingGoal::Lookups);
+
+    for idx in 0..8_usize {
+        let inputs = ["a", "b", "c"]
+            .into_iter()
+            .enumerate()
+            .map(|(bit, name)| (name.to_string(), (idx >> bit) & 1))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            mapped.evaluate("top", &inputs),
+            original.evaluate("top", &inputs)
+        );
+    }
+}
+
+#[test]
+fn test_mapped_constants() {
+    // `y = (a & 1) ^ (b | 0)`, and `z = !0 & 1` which doesn't depend on any input
+    let netlist: Netlist = serde_json::from_str(
+        r#"{
+            "creator": "hand-written",
+            "modules": {
+                "top": {
+                    "attributes": { "top": "00000000000000000000000000000001" },
+                    "ports": {
+                        "a": { "direction": "input", "bits": [ 2 ] },
+                        "b": { "direction": "input", "bits": [ 3 ] },
+                        "y": { "direction": "output", "bits": [ 6 ] },
+                        "z": { "direction": "output", "bits": [ 8 ] }
+                    },
+                    "cells": {
+                        "and": {
+                            "type": "$_AND_",
+                            "port_directions": { "A": "input", "B": "input", "Y": "output" },
+                            "connections": { "A": [ 2 ], "B": [ "1" ], "Y": [ 4 ] }
+                        },
+                        "or": {
+                            "type": "$_OR_",
+                            "port_directions": { "A": "input", "B": "input", "Y": "output" },
+                            "connections": { "A": [ 3 ], "B": [ "0" ], "Y": [ 5 ] }
+                        },
+                        "xor": {
+                            "type": "$_XOR_",
+                            "port_directions": { "A": "input", "B": "input", "Y": "output" },
+                            "connections": { "A": [ 4 ], "B": [ 5 ], "Y": [ 6 ] }
+                        },
+                        "not": {
+                            "type": "$_NOT_",
+                            "port_directions": { "A": "input", "Y": "output" },
+                            "connections": { "A": [ "0" ], "Y": [ 7 ] }
+                        },
+                        "const_and": {
+                            "type": "$_AND_",
+                            "port_directions": { "A": "input", "B": "input", "Y": "output" },
+                            "connections": { "A": [ 7 ], "B": [ "1" ], "Y": [ 8 ] }
+                        }
+                    }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let luts = [Lut::not(), Lut::or(), Lut::and(), Lut::xor(), Lut::mux()]
+        .into_iter()
+        .map(|lut| (lut.name.clone(), lut))
+        .collect::<Map<_, _>>();
+    let module = netlist.modules.get("top").unwrap();
+    let (mapped, new_luts) = map_module(module, &luts, 4, MappingGoal::Lookups);
+
+    // The constants end up in the table of a single xor of the inputs
+    assert_eq!(mapped.cells.len(), 1);
+    assert!(
+        mapped
+            .cells
+            .values()
+            .flat_map(|cell| cell.connections.values())
+            .all(|wire| !matches!(wire, Wire::Const(_)))
+    );
+    assert!(new_luts.iter().all(|lut| lut.input_names.len() == 2));
+    assert_eq!(mapped.ports.get("z").unwrap().wire, Wire::Const(true));
+
+    let original = Registry::default().add_netlist(netlist.clone());
+    let mapped = Registry::default()
+        .add_netlist(netlist)
+        .map_luts(4, MappingGoal::Lookups);
+    for idx in 0..4_usize {
+        let inputs = ["a", "b"]
+            .into_iter()
+            .enumerate()
+            .map(|(bit, name)| (name.to_string(), (idx >> bit) & 1))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            mapped.evaluate("top", &inputs),
+            original.evaluate("top", &inputs)
+        );
+    }
+}
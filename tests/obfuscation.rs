@@ -0,0 +1,159 @@
+mod common;
+
+use std::fs;
+
+use vlogpp::harness::preprocess;
+use vlogpp::lut::Lut;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_obfuscation_modes() {
+    let outputs = (0..8_usize)
+        .map(|idx| idx.count_ones() >= 2)
+        .collect::<Vec<_>>();
+
+    let mut results = Vec::new();
+    for obfuscation in [
+        ObfuscationConfig::readable(),
+        ObfuscationConfig::obfuscated(),
+    ] {
+        let registry = Registry::default().register_lut(Lut::new(
+            "majority",
+            &["a", "b", "c"],
+            &["y"],
+            &outputs,
+        ));
+        let mut global_scope = GlobalScope::new(registry, obfuscation.clone());
+        let majority = Registry::module(&mut global_scope, "majority").unwrap();
+
+        let macro_text = global_scope.emit();
+        let name = global_scope.get_macro(majority).name.clone();
+        assert_eq!(!obfuscation.enabled, name == "MAJORITY");
+        assert_eq!(
+            !obfuscation.enabled,
+            macro_text.contains("// Module: `majority`")
+        );
+
+        let calls = (0..8_usize)
+            .map(|idx| format!("{name}({}, {}, {})\n", idx >> 2, (idx >> 1) & 1, idx & 1))
+            .collect::<String>();
+        results.push(preprocess(&format!("{macro_text}\n{calls}")).unwrap());
+    }
+
+    assert_eq!(results[0], results[1]);
+    assert_eq!(
+        results[0].replace(" ", ""),
+        outputs
+            .iter()
+            .map(|&output| if output { "1\n" } else { "0\n" })
+            .collect::<String>()
+    );
+}
+
+#[test]
+fn test_seeded_obfuscation() {
+    let emit = |obfuscation: ObfuscationConfig| {
+        let outputs = (0..16_usize)
+            .map(|idx| idx.count_ones() % 2 == 1)
+            .collect::<Vec<_>>();
+        let registry = Registry::default()
+            .limit_lut_inputs(2)
+            .register_lut(Lut::new("parity", &["a", "b", "c", "d"], &["y"], &outputs));
+
+        let mut global_scope = GlobalScope::new(registry, obfuscation);
+        Registry::module(&mut global_scope, "parity").unwrap();
+        Registry::while_macro(&mut global_scope);
+        Registry::repeat_n_macro(&mut global_scope, 4);
+        global_scope.variadicify_macros(2);
+        global_scope.emit()
+    };
+
+    let seeded = emit(ObfuscationConfig::obfuscated().seed(7));
+    assert_eq!(seeded, emit(ObfuscationConfig::obfuscated().seed(7)));
+    assert_ne!(seeded, emit(ObfuscationConfig::obfuscated().seed(8)));
+    assert_eq!(
+        emit(ObfuscationConfig::readable().seed(7)),
+        emit(ObfuscationConfig::readable())
+    );
+}
+
+#[test]
+fn test_deobfuscation() {
+    let outputs = (0..16_usize)
+        .map(|idx| idx.count_ones() % 2 == 1 || idx == 12)
+        .collect::<Vec<_>>();
+    let emit = |obfuscation: ObfuscationConfig| {
+        let registry = Registry::default()
+            .limit_lut_inputs(2)
+            .register_lut(Lut::new("mixed", &["a", "b", "c", "d"], &["y"], &outputs));
+
+        let mut global_scope = GlobalScope::new(registry, obfuscation);
+        let mixed = Registry::module(&mut global_scope, "mixed").unwrap();
+        Registry::while_macro(&mut global_scope);
+        let name = global_scope.get_macro(mixed).name.clone();
+        (global_scope.emit(), name, global_scope.obfuscation_key())
+    };
+    let normalize = |text: &str| {
+        let mut lines = text
+            .lines()
+            .filter(|line| !line.starts_with("//"))
+            .map(|line| line.replace(" ", ""))
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines
+    };
+
+    let (readable, _, readable_key) = emit(ObfuscationConfig::readable());
+    let (obfuscated, name, key) = emit(ObfuscationConfig::obfuscated());
+    assert!(readable_key.names.is_empty());
+    assert_eq!(
+        normalize(&key.deobfuscate(&obfuscated)),
+        normalize(&readable)
+    );
+
+    let entry = key.names.get(&name).unwrap();
+    assert_eq!(entry.name, "MIXED");
+    assert_eq!(entry.origin, "split 0 of `mixed`");
+    assert_eq!(
+        key.deobfuscate(&format!(
+            "error: unterminated argument list invoking macro \"{name}\""
+        )),
+        "error: unterminated argument list invoking macro \"MIXED\""
+    );
+}
+
+#[test]
+fn test_scrambled_wires() {
+    let netlist = || {
+        serde_json::from_str::<Netlist>(&fs::read_to_string("tests/gates.json").unwrap()).unwrap()
+    };
+    let original = Registry::default().add_netlist(netlist());
+
+    for (seed, registry) in [
+        Registry::default(),
+        Registry::default().limit_lut_inputs(2),
+        Registry::default().propagate_x(),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let obfuscation = ObfuscationConfig::obfuscated()
+            .seed(seed as u64)
+            .scramble_wires(true);
+        let mut global_scope = GlobalScope::new(registry.add_netlist(netlist()), obfuscation);
+        let tops = Registry::top_modules(&mut global_scope);
+        global_scope.variadicify_macros(1);
+
+        let key = global_scope.obfuscation_key();
+        assert!(
+            key.names
+                .values()
+                .any(|entry| entry.origin.contains(" on wire"))
+        );
+
+        common::assert_matches_simulation(&global_scope, &tops, &original);
+    }
+}
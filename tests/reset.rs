@@ -0,0 +1,96 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::Command;
+
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_reset() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/reset.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = Registry::module(&mut global_scope, "reset").unwrap();
+    let cycle_counts = [1_usize, 5];
+    let reset_macros = cycle_counts
+        .iter()
+        .map(|&cycles| {
+            let cycle_macro = Registry::cycle_macro(&mut global_scope, top, cycles);
+            Registry::reset_macro(&mut global_scope, "reset", cycle_macro).unwrap()
+        })
+        .collect::<Vec<_>>();
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    let top_macro = global_scope.get_macro(top);
+    let output_map = global_scope
+        .get_scope(top_macro.scope_id)
+        .local()
+        .output_names
+        .clone()
+        .unwrap();
+
+    for (&cycles, &reset_macro) in cycle_counts.iter().zip(&reset_macros) {
+        // The count resets to 0 and the submodule starts from its initial value of 3
+        let main_out = (0..cycles).fold(0, |acc, cycle| (acc + (3 + cycle) % 16) % 256);
+        let mut output_bits = format!("{:08b}", main_out)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, char)| (format!("cnt[{idx}]"), char))
+            .chain(
+                format!("{:04b}", (3 + cycles) % 16)
+                    .chars()
+                    .rev()
+                    .enumerate()
+                    .map(|(idx, char)| (format!("sub..sub_cnt[{idx}]"), char)),
+            )
+            .map(|(name, char)| {
+                (
+                    output_map.iter().position(|out| out == &name).unwrap(),
+                    char,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        output_bits.sort_by_key(|(idx, _)| *idx);
+
+        let text = format!(
+            "{macro_text}\n{}(0)",
+            &global_scope.get_macro(reset_macro).name
+        );
+
+        let mut file = File::create("test_reset.h").unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let status = Command::new("gcc")
+            .arg("-E")
+            .arg("-P")
+            .arg("test_reset.h")
+            .arg("-o")
+            .arg("test_reset_out.h")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let actual = fs::read_to_string("test_reset_out.h").unwrap();
+        assert_eq!(
+            actual.replace(" ", ""),
+            format!(
+                "{}\n",
+                output_bits
+                    .iter()
+                    .map(|(_, out)| out.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        );
+    }
+}
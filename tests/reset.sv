@@ -0,0 +1,36 @@
+`default_nettype none
+
+module reset (
+    input var logic clk,
+    input var logic rst,
+    output var logic [7:0] cnt
+);
+
+    logic [3:0] sub_out;
+    reset_sub sub (
+        .clk(clk),
+        .sub_cnt(sub_out)
+    );
+
+    always_ff @(posedge clk) begin
+        if (rst) begin
+            cnt <= 8'd0;
+        end else begin
+            cnt <= cnt + {4'd0, sub_out};
+        end
+    end
+
+endmodule
+
+module reset_sub (
+    input var logic clk,
+    output var logic [3:0] sub_cnt
+);
+
+    initial sub_cnt = 4'd3;
+
+    always_ff @(posedge clk) begin
+        sub_cnt <= sub_cnt + 1;
+    end
+
+endmodule
@@ -0,0 +1,140 @@
+mod common;
+
+use std::fs;
+
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use vlogpp::Set;
+use vlogpp::r#macro::MacroID;
+use vlogpp::mapping::MappingGoal;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::rewrite::rewrite_module;
+use vlogpp::scope::global::GlobalScope;
+
+fn netlist() -> Netlist {
+    serde_json::from_str(&fs::read_to_string("tests/gates.json").unwrap()).unwrap()
+}
+
+fn gates(names: &[&str]) -> Set<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn test_rewrite_equivalence() {
+    let original = Registry::default().add_netlist(netlist());
+    let all = gates(&["$_NOT_", "$_AND_", "$_OR_", "$_XOR_", "$_MUX_"]);
+
+    for (intensity, x_safe) in [(0.5, false), (1.0, false), (1.0, true)] {
+        for seed in 0..16 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut rewritten = netlist();
+            for module in rewritten.modules.values_mut() {
+                let new_module = rewrite_module(module, &all, intensity, x_safe, &mut rng);
+                if intensity == 1.0 {
+                    // Every gate is replaced, apart from multiplexers which could hide an `X`
+                    assert!(module.cells.iter().all(|(name, cell)| {
+                        (x_safe && cell.kind == "$_MUX_") == new_module.cells.contains_key(name)
+                    }));
+                }
+                *module = new_module;
+            }
+
+            let names = netlist()
+                .modules
+                .iter()
+                .map(|(name, module)| {
+                    (
+                        name.clone(),
+                        module
+                            .input_ports()
+                            .map(|(port, _)| port.clone())
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let rewritten = Registry::default().add_netlist(rewritten);
+            for (name, inputs) in names {
+                for inputs in common::all_inputs(&inputs) {
+                    assert_eq!(
+                        rewritten.evaluate(&name, &inputs),
+                        original.evaluate(&name, &inputs)
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rewrite_available_gates() {
+    let original = Registry::default().add_netlist(netlist());
+    let available = gates(&["$_NOT_", "$_AND_", "$_OR_"]);
+
+    for seed in 0..16 {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut rewritten = netlist();
+        for module in rewritten.modules.values_mut() {
+            let new_module = rewrite_module(module, &available, 1.0, false, &mut rng);
+            // Only the available gates are added
+            assert!(new_module.cells.iter().all(|(name, cell)| {
+                module.cells.contains_key(name) || available.contains(&cell.kind)
+            }));
+            *module = new_module;
+        }
+
+        let rewritten = Registry::default().add_netlist(rewritten);
+        for (name, module) in &netlist().modules {
+            let inputs = module
+                .input_ports()
+                .map(|(port, _)| port.clone())
+                .collect::<Vec<_>>();
+            for inputs in common::all_inputs(&inputs) {
+                assert_eq!(
+                    rewritten.evaluate(name, &inputs),
+                    original.evaluate(name, &inputs)
+                );
+            }
+        }
+    }
+}
+
+fn emit_rewritten(registry: Registry, intensity: f64) -> (GlobalScope, Vec<MacroID>, String) {
+    let obfuscation = ObfuscationConfig::readable()
+        .logic_intensity(intensity)
+        .seed(5);
+    let mut global_scope = GlobalScope::new(registry, obfuscation);
+    let tops = Registry::top_modules(&mut global_scope);
+    global_scope.variadicify_macros(1);
+    let macro_text = global_scope.emit();
+    (global_scope, tops, macro_text)
+}
+
+#[test]
+fn test_rewrite_macros() {
+    let original = Registry::default().add_netlist(netlist());
+    check_macros(&original, Registry::default().add_netlist(netlist()));
+}
+
+#[test]
+fn test_rewrite_mapped_macros() {
+    let original = Registry::default().add_netlist(netlist());
+    let mapped = || {
+        Registry::default()
+            .add_netlist(netlist())
+            .map_luts(3, MappingGoal::Lookups)
+    };
+
+    // The mapped luts still get decoys, even though the gates they replaced aren't registered
+    assert_ne!(
+        emit_rewritten(mapped(), 1.0).2,
+        emit_rewritten(mapped(), 0.0).2
+    );
+    check_macros(&original, mapped());
+}
+
+fn check_macros(original: &Registry, registry: Registry) {
+    let (global_scope, tops, _) = emit_rewritten(registry, 1.0);
+    common::assert_matches_simulation(&global_scope, &tops, original);
+}
@@ -0,0 +1,265 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::Command;
+
+use vlogpp::halt::HaltCondition;
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_run() {
+    let cont = HaltCondition::Low("cont".to_string());
+    check_run(
+        "run",
+        Registry::default(),
+        &cont,
+        16,
+        &[0, 9, 12],
+        |_, cont| !cont,
+    );
+}
+
+#[test]
+fn test_run_exact() {
+    // Starting from 12 takes exactly 14 cycles
+    let cont = HaltCondition::Low("cont".to_string());
+    check_run(
+        "run_exact",
+        Registry::default().eval_branching(3),
+        &cont,
+        14,
+        &[9, 12],
+        |_, cont| !cont,
+    );
+}
+
+#[test]
+fn test_run_halt() {
+    let equals = HaltCondition::Equals(vec![
+        ("cnt[3]".to_string(), true),
+        ("cnt[2]".to_string(), true),
+        ("cnt[1]".to_string(), false),
+        ("cnt[0]".to_string(), false),
+    ]);
+    check_run(
+        "run_equals",
+        Registry::default(),
+        &equals,
+        16,
+        &[0, 12, 13],
+        |cnt, _| cnt == 12,
+    );
+
+    let any = HaltCondition::Any(vec!["cnt[2]".to_string(), "cont".to_string()]);
+    check_run(
+        "run_any",
+        Registry::default(),
+        &any,
+        16,
+        &[0, 3, 9],
+        |cnt, cont| cnt & 4 != 0 || cont,
+    );
+}
+
+#[test]
+fn test_run_unknown_output() {
+    let netlist = Netlist::new("tests/run.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    let halt = HaltCondition::High("done".to_string());
+    assert!(Registry::run_macro(&mut global_scope, top, &halt, 16).is_err());
+}
+
+fn check_run(
+    name: &str,
+    registry: Registry,
+    halt: &HaltCondition,
+    max_cycles: usize,
+    starts: &[usize],
+    halts: impl Fn(usize, bool) -> bool,
+) {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/run.sv", false, &[]).unwrap();
+    let registry = registry.add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    let run = Registry::run_macro(&mut global_scope, top, halt, max_cycles).unwrap();
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    let top_macro = global_scope.get_macro(top);
+    let output_map = global_scope
+        .get_scope(top_macro.scope_id)
+        .local()
+        .output_names
+        .clone()
+        .unwrap();
+
+    for &start in starts {
+        let mut inputs = format!("{:04b}", start)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, value)| {
+                (
+                    top_macro
+                        .input_position(&format!("cnt[{idx}].i"), &global_scope)
+                        .unwrap(),
+                    value,
+                )
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|(idx, _)| *idx);
+
+        // The outputs of each cycle are the incremented count and whether it didn't start at 9
+        let mut cnt = start;
+        let mut cont;
+        loop {
+            cont = cnt != 9;
+            cnt = (cnt + 1) % 16;
+            if halts(cnt, cont) {
+                break;
+            }
+        }
+
+        let mut output_bits = format!("{:04b}", cnt)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, char)| (format!("cnt[{idx}]"), char))
+            .chain(std::iter::once((
+                "cont".to_string(),
+                if cont { '1' } else { '0' },
+            )))
+            .map(|(name, char)| {
+                (
+                    output_map.iter().position(|out| out == &name).unwrap(),
+                    char,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        output_bits.sort_by_key(|(idx, _)| *idx);
+
+        let text = format!(
+            "{macro_text}\n{}({})",
+            &global_scope.get_macro(run).name,
+            inputs
+                .iter()
+                .map(|(_, var)| var.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let path = format!("test_{name}.h");
+        let out_path = format!("test_{name}_out.h");
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let status = Command::new("gcc")
+            .arg("-E")
+            .arg("-P")
+            .arg(&path)
+            .arg("-o")
+            .arg(&out_path)
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let actual = fs::read_to_string(&out_path).unwrap();
+        assert_eq!(
+            actual.replace(" ", ""),
+            format!(
+                "{}\n",
+                output_bits
+                    .iter()
+                    .map(|(_, out)| out.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        );
+    }
+}
+
+#[test]
+fn test_run_trace() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/run.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    let cont = HaltCondition::Low("cont".to_string());
+    let traced = ["cnt[3]", "cnt[2]", "cnt[1]", "cnt[0]"];
+    let run = Registry::run_trace_macro(&mut global_scope, top, &cont, Some(&traced), 16).unwrap();
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    let top_macro = global_scope.get_macro(top);
+
+    let start = 5_usize;
+    let mut inputs = format!("{:04b}", start)
+        .chars()
+        .rev()
+        .enumerate()
+        .map(|(idx, value)| {
+            (
+                top_macro
+                    .input_position(&format!("cnt[{idx}].i"), &global_scope)
+                    .unwrap(),
+                value,
+            )
+        })
+        .collect::<Vec<_>>();
+    inputs.sort_by_key(|(idx, _)| *idx);
+
+    let expected = (start + 1..=10)
+        .map(|cnt| {
+            format!(
+                "({})",
+                format!("{cnt:04b}")
+                    .chars()
+                    .map(|char| char.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        })
+        .collect::<String>();
+
+    let text = format!(
+        "{macro_text}\n{}({})",
+        &global_scope.get_macro(run).name,
+        inputs
+            .iter()
+            .map(|(_, var)| var.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut file = File::create("test_trace.h").unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+
+    let status = Command::new("gcc")
+        .arg("-E")
+        .arg("-P")
+        .arg("test_trace.h")
+        .arg("-o")
+        .arg("test_trace_out.h")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let actual = fs::read_to_string("test_trace_out.h").unwrap();
+    assert_eq!(actual.replace(" ", ""), format!("{expected}\n"));
+}
@@ -0,0 +1,15 @@
+`default_nettype none
+
+module run (
+    input var logic clk,
+    output var logic [3:0] cnt,
+    output var logic cont
+);
+
+    always_ff @(posedge clk) begin
+        cnt <= cnt + 1;
+    end
+
+    assign cont = cnt != 4'd9;
+
+endmodule
@@ -0,0 +1,54 @@
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_state_layout() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/submod_state.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    global_scope.variadicify_macros(2);
+
+    let top_macro = global_scope.get_macro(top);
+    let output_map = global_scope
+        .get_scope(top_macro.scope_id)
+        .local()
+        .output_names
+        .clone()
+        .unwrap();
+
+    let layout = global_scope.state_layout(top);
+    assert_eq!(layout.elements.len(), 2);
+
+    for (name, path, width) in [("cnt", vec![], 8), ("sub..sub_cnt", vec!["sub"], 4)] {
+        let element = layout.element(name).unwrap();
+        assert_eq!(element.path, path);
+        assert_eq!(element.width(), width);
+        assert_eq!(element.bits, (0..width).collect::<Vec<_>>());
+
+        for bit in 0..width {
+            assert_eq!(
+                element.input_positions[bit],
+                top_macro
+                    .input_position(&format!("{name}[{bit}].i"), &global_scope)
+                    .unwrap()
+            );
+            assert_eq!(
+                element.output_positions[bit],
+                output_map
+                    .iter()
+                    .position(|output| output == &format!("{name}[{bit}]"))
+                    .unwrap()
+            );
+            assert_eq!(element.input_port(bit), format!("{name}[{bit}].i"));
+        }
+    }
+
+    assert_eq!(layout.feedback().count(), 12);
+}
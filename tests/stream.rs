@@ -0,0 +1,127 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::Command;
+
+use vlogpp::lint::lint_directory;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+#[test]
+fn test_stream() {
+    lint_directory("tests").unwrap();
+
+    let netlist = Netlist::new("tests/stream.sv", false, &[]).unwrap();
+    let registry = Registry::default().add_netlist(netlist);
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
+    let top = *Registry::top_modules(&mut global_scope).first().unwrap();
+    let stimulus = ["data[3]", "data[2]", "data[1]", "data[0]"];
+    let run = Registry::run_stream_macro(&mut global_scope, top, &stimulus, 8).unwrap();
+    global_scope.variadicify_macros(2);
+
+    let macro_text = global_scope.emit();
+    let run_macro = global_scope.get_macro(run);
+    let top_macro = global_scope.get_macro(top);
+    let output_map = global_scope
+        .get_scope(top_macro.scope_id)
+        .local()
+        .output_names
+        .clone()
+        .unwrap();
+
+    for data in [vec![], vec![3_usize], vec![7, 12, 1, 9, 15, 4, 0, 11]] {
+        let start = 6_usize;
+        let mut inputs = format!("{:04b}", start)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, value)| {
+                (
+                    run_macro
+                        .input_position(&format!("sum[{idx}].i"), &global_scope)
+                        .unwrap(),
+                    value.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|(idx, _)| *idx);
+
+        let seq = data
+            .iter()
+            .map(|value| {
+                format!(
+                    "({})",
+                    format!("{value:04b}")
+                        .chars()
+                        .map(|char| char.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+            .collect::<String>();
+
+        let sum = data.iter().fold(start, |acc, value| (acc + value) % 16);
+        let mut output_bits = format!("{:04b}", sum)
+            .chars()
+            .rev()
+            .enumerate()
+            .map(|(idx, char)| {
+                (
+                    output_map
+                        .iter()
+                        .position(|out| out == &format!("sum[{idx}]"))
+                        .unwrap(),
+                    char,
+                )
+            })
+            .collect::<Vec<_>>();
+        output_bits.sort_by_key(|(idx, _)| *idx);
+
+        let text = format!(
+            "{macro_text}\n{}({}, {seq})",
+            &run_macro.name,
+            inputs
+                .iter()
+                .map(|(_, value)| value.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut file = File::create("test_stream.h").unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let status = Command::new("gcc")
+            .arg("-E")
+            .arg("-P")
+            .arg("test_stream.h")
+            .arg("-o")
+            .arg("test_stream_out.h")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let actual = fs::read_to_string("test_stream_out.h").unwrap();
+        assert_eq!(
+            actual.replace(" ", ""),
+            format!(
+                "{}\n",
+                output_bits
+                    .iter()
+                    .map(|(_, out)| out.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        );
+    }
+}
+
+#[test]
+fn test_stream_unknown_input() {
+    let mut global_scope = GlobalScope::new(Registry::default(), ObfuscationConfig::default());
+    let and = Registry::module(&mut global_scope, "$_AND_").unwrap();
+    assert!(Registry::stream_macro(&mut global_scope, and, &["A", "C"]).is_err());
+    assert!(Registry::run_stream_macro(&mut global_scope, and, &["C"], 4).is_err());
+}
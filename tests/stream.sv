@@ -0,0 +1,13 @@
+`default_nettype none
+
+module stream (
+    input var logic clk,
+    input var logic [3:0] data,
+    output var logic [3:0] sum
+);
+
+    always_ff @(posedge clk) begin
+        sum <= sum + data;
+    end
+
+endmodule
@@ -5,17 +5,18 @@ use std::process::Command;
 
 use vlogpp::lint::lint_directory;
 use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
 use vlogpp::registry::Registry;
 use vlogpp::scope::global::GlobalScope;
 
 #[test]
 fn test_submods() {
-    lint_directory("tests");
+    lint_directory("tests").unwrap();
 
-    let netlist = Netlist::new("tests/submod.sv", false, &[]);
+    let netlist = Netlist::new("tests/submod.sv", false, &[]).unwrap();
     let registry = Registry::default().add_netlist(netlist);
 
-    let mut global_scope = GlobalScope::new(registry);
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::default());
     let top = *Registry::top_modules(&mut global_scope).first().unwrap();
     global_scope.variadicify_macros(2);
 
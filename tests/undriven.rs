@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use vlogpp::mapping::MappingGoal;
+use vlogpp::netlist::Netlist;
+use vlogpp::obfuscation::ObfuscationConfig;
+use vlogpp::registry::Registry;
+use vlogpp::scope::global::GlobalScope;
+
+const ALPHABET: [&str; 3] = ["0", "1", "X"];
+
+fn netlist() -> Netlist {
+    serde_json::from_str(&fs::read_to_string("tests/undriven.json").unwrap()).unwrap()
+}
+
+/// `y = ((a & x) | b) & c` with an undriven `x`, and `z = a ^ b`
+fn expected(inputs: &[usize]) -> [usize; 2] {
+    let [a, b, c] = inputs else { unreachable!() };
+    let a_undef = if *a == 0 { 0 } else { 2 };
+    let or_b = if *b == 1 { 1 } else { a_undef.max(*b) };
+    let y = match (or_b, c) {
+        (0, _) | (_, 0) => 0,
+        (1, 1) => 1,
+        _ => 2,
+    };
+    let z = if *a == 2 || *b == 2 { 2 } else { a ^ b };
+    [y, z]
+}
+
+#[test]
+fn test_undriven_x() {
+    let inputs = (0..27_usize)
+        .map(|idx| vec![idx / 9, (idx / 3) % 3, idx % 3])
+        .collect::<Vec<_>>();
+
+    let simulated = Registry::default().propagate_x().add_netlist(netlist());
+    for values in &inputs {
+        let outputs = simulated
+            .evaluate(
+                "undriven",
+                &["a", "b", "c"]
+                    .into_iter()
+                    .map(str::to_string)
+                    .zip(values.iter().copied())
+                    .collect(),
+            )
+            .unwrap();
+        let [y, z] = expected(values);
+        assert_eq!(
+            outputs,
+            HashMap::from([("y".to_string(), y), ("z".to_string(), z)])
+        );
+    }
+
+    for registry in [
+        Registry::default().propagate_x().add_netlist(netlist()),
+        Registry::default()
+            .propagate_x()
+            .add_netlist(netlist())
+            .collapse_modules(3),
+        Registry::default()
+            .add_netlist(netlist())
+            .map_luts(4, MappingGoal::Lookups)
+            .propagate_x(),
+        Registry::default()
+            .propagate_x()
+            .add_netlist(netlist())
+            .map_luts(2, MappingGoal::Depth),
+    ] {
+        let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::readable());
+        let undriven = Registry::module(&mut global_scope, "undriven").unwrap();
+        let r#macro = global_scope.get_macro(undriven);
+        let positions =
+            ["a", "b", "c"].map(|name| r#macro.input_position(name, &global_scope).unwrap());
+
+        let mut calls = String::new();
+        let mut results = String::new();
+        for values in &inputs {
+            let mut args = [""; 3];
+            for (&position, &value) in positions.iter().zip(values) {
+                args[position] = ALPHABET[value];
+            }
+            calls.push_str(&format!("{}({})\n", r#macro.name, args.join(", ")));
+            results.push_str(&expected(values).map(|value| ALPHABET[value]).join(","));
+            results.push('\n');
+        }
+
+        let mut child = Command::new("gcc")
+            .args(["-E", "-P", "-x", "c", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(format!("{}\n{calls}", global_scope.emit()).as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().replace(" ", ""),
+            results
+        );
+    }
+}
+
+#[test]
+fn test_undriven_binary_collapse() {
+    // Without `propagate_x`, undriven bits are 0, so `y = b & c`
+    let registry = Registry::default()
+        .add_netlist(netlist())
+        .collapse_modules(3);
+    for idx in 0..8_usize {
+        let [a, b, c] = [idx >> 2, (idx >> 1) & 1, idx & 1];
+        let outputs = registry
+            .evaluate(
+                "undriven",
+                &HashMap::from([
+                    ("a".to_string(), a),
+                    ("b".to_string(), b),
+                    ("c".to_string(), c),
+                ]),
+            )
+            .unwrap();
+        assert_eq!(
+            outputs,
+            HashMap::from([("y".to_string(), b & c), ("z".to_string(), a ^ b)])
+        );
+    }
+
+    let mut global_scope = GlobalScope::new(registry, ObfuscationConfig::readable());
+    assert!(Registry::module(&mut global_scope, "undriven").is_some());
+}